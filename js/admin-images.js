@@ -0,0 +1,75 @@
+function updateImagePreivew() {
+  const filePicker = document.getElementById('img');
+  const imgElement = document.getElementById('thumbnail_crop_preview');
+
+  if (!filePicker.files.length) {
+    imgElement.src = '';
+  } else {
+    imgElement.src = URL.createObjectURL(filePicker.files.item(0));
+
+    const cropper = new Cropper(imgElement, {
+      // View Mode:
+      // Restrict the minimum canvas size to fit within the container.
+      // If the proportions of the canvas and the container differ, the minimum canvas will be surrounded by extra space in one of the dimensions.
+      viewMode: 2,
+      zoomable: false,
+      rotatable: false,
+      scalable: false,
+      aspectRatio: 1,
+      crop(event) {
+        const cropHiddenValue = document.getElementById('thumbnail_crop_rect');
+
+        const rect = {
+          x: event.detail.x,
+          y: event.detail.y,
+          width: event.detail.width,
+          height: event.detail.height,
+        };
+
+        cropHiddenValue.value = JSON.stringify(rect);
+      },
+    });
+
+  }
+}
+
+function handleFileChange() {
+  updateImagePreivew();
+}
+
+const filePicker = document.getElementById('img');
+filePicker.onchange = handleFileChange;
+
+function toggleCropThumbnail(e) {
+  updateImagePreivew();
+
+  if (!e.target.checked) {
+    const cropHiddenValue = document.getElementById('thumbnail_crop_rect');
+
+    cropHiddenValue.value = undefined;
+  }
+}
+
+// One name per file for bulk uploads, matched to the files by position
+function updateBulkNames() {
+  const bulkPicker = document.getElementById('bulk_img');
+  const names = document.getElementById('bulk_names');
+
+  names.replaceChildren();
+
+  Array.from(bulkPicker.files).forEach((file, i) => {
+    const label = document.createElement('label');
+    label.htmlFor = `bulk_name_${i}`;
+    label.textContent = `Name for ${file.name}:`;
+
+    const input = document.createElement('input');
+    input.type = 'text';
+    input.id = `bulk_name_${i}`;
+    input.name = 'name';
+    input.value = file.name.replace(/\.[^.]+$/, '');
+
+    names.append(label, input);
+  });
+}
+
+document.getElementById('bulk_img').onchange = updateBulkNames;
@@ -0,0 +1,14 @@
+// Behaviour shared by the admin pages, kept out of the templates so the
+// Content-Security-Policy doesn't need to allow inline scripts.
+
+document.querySelectorAll('form[data-confirm]').forEach((form) => {
+  form.addEventListener('submit', (e) => {
+    if (!confirm(form.dataset.confirm)) {
+      e.preventDefault();
+    }
+  });
+});
+
+document.querySelectorAll('input[data-autosubmit]').forEach((input) => {
+  input.addEventListener('change', () => input.form.submit());
+});
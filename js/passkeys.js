@@ -0,0 +1,98 @@
+// Browser side of the passkey registration and login ceremonies. The server
+// sends and expects binary values as base64url strings.
+
+function base64urlToBuffer(value) {
+  const base64 = value.replace(/-/g, '+').replace(/_/g, '/');
+  const padded = base64 + '='.repeat((4 - (base64.length % 4)) % 4);
+  return Uint8Array.from(atob(padded), (c) => c.charCodeAt(0)).buffer;
+}
+
+function bufferToBase64url(buffer) {
+  const bytes = String.fromCharCode(...new Uint8Array(buffer));
+  return btoa(bytes).replace(/\+/g, '-').replace(/\//g, '_').replace(/=+$/, '');
+}
+
+async function postJson(url, body) {
+  const response = await fetch(url, {
+    method: 'POST',
+    credentials: 'same-origin',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify(body),
+  });
+
+  if (!response.ok) {
+    throw new Error(await response.text());
+  }
+
+  return response.status === 204 ? null : response.json();
+}
+
+function decodeCredentialList(credentials) {
+  return credentials.map((c) => ({ ...c, id: base64urlToBuffer(c.id) }));
+}
+
+async function registerPasskey(name) {
+  const options = await postJson('/admin/passkeys/register/start', {});
+
+  options.challenge = base64urlToBuffer(options.challenge);
+  options.user.id = base64urlToBuffer(options.user.id);
+  options.excludeCredentials = decodeCredentialList(options.excludeCredentials);
+
+  const credential = await navigator.credentials.create({ publicKey: options });
+
+  await postJson('/admin/passkeys/register/finish', {
+    name,
+    id: bufferToBase64url(credential.rawId),
+    clientDataJSON: bufferToBase64url(credential.response.clientDataJSON),
+    attestationObject: bufferToBase64url(credential.response.attestationObject),
+  });
+}
+
+async function loginWithPasskey(username) {
+  const options = await postJson('/admin/login/passkey/start', { username });
+
+  options.challenge = base64urlToBuffer(options.challenge);
+  options.allowCredentials = decodeCredentialList(options.allowCredentials);
+
+  const credential = await navigator.credentials.get({ publicKey: options });
+
+  return postJson('/admin/login/passkey/finish', {
+    id: bufferToBase64url(credential.rawId),
+    clientDataJSON: bufferToBase64url(credential.response.clientDataJSON),
+    authenticatorData: bufferToBase64url(credential.response.authenticatorData),
+    signature: bufferToBase64url(credential.response.signature),
+  });
+}
+
+function showPasskeyError(error) {
+  const errorElement = document.getElementById('passkey_error');
+  errorElement.textContent = error.message;
+}
+
+const registerForm = document.getElementById('register_passkey_form');
+if (registerForm) {
+  registerForm.addEventListener('submit', async (e) => {
+    e.preventDefault();
+
+    try {
+      await registerPasskey(document.getElementById('passkey_name').value);
+      window.location.reload();
+    } catch (error) {
+      showPasskeyError(error);
+    }
+  });
+}
+
+const loginForm = document.getElementById('passkey_login_form');
+if (loginForm) {
+  loginForm.addEventListener('submit', async (e) => {
+    e.preventDefault();
+
+    try {
+      const result = await loginWithPasskey(document.getElementById('username').value);
+      window.location.href = result.redirect;
+    } catch (error) {
+      showPasskeyError(error);
+    }
+  });
+}
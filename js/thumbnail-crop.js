@@ -0,0 +1,49 @@
+const imgElement = document.getElementById('thumbnail_crop_preview');
+
+// The crop saved for this thumbnail, if there is one, to start from
+const savedCrop = JSON.parse(imgElement.dataset.crop);
+
+const cropper = new Cropper(imgElement, {
+  // View Mode:
+  // Restrict the minimum canvas size to fit within the container.
+  // If the proportions of the canvas and the container differ, the minimum canvas will be surrounded by extra space in one of the dimensions.
+  viewMode: 2,
+  zoomable: false,
+  rotatable: false,
+  scalable: false,
+  aspectRatio: Number(imgElement.dataset.aspectRatio),
+  data: savedCrop || undefined,
+  crop(event) {
+    const cropHiddenValue = document.getElementById('thumbnail_crop_rect');
+
+    const rect = {
+      x: event.detail.x,
+      y: event.detail.y,
+      width: event.detail.width,
+      height: event.detail.height,
+    };
+
+    cropHiddenValue.value = JSON.stringify(rect);
+  },
+});
+
+// Makes the thumbnail for the current crop without saving it, kept alongside
+// earlier ones to compare
+document.getElementById('thumbnail_preview_button').addEventListener('click', async () => {
+  const form = document.getElementById('thumbnail_crop_form');
+
+  const response = await fetch('/admin/images/preview-thumbnail', {
+    method: 'POST',
+    body: new URLSearchParams(new FormData(form)),
+  });
+  if (!response.ok) {
+    alert(await response.text());
+    return;
+  }
+
+  const preview = document.createElement('img');
+  preview.src = URL.createObjectURL(await response.blob());
+  preview.title = document.getElementById('thumbnail_crop_rect').value;
+
+  document.getElementById('thumbnail_previews').prepend(preview);
+});
@@ -0,0 +1,17 @@
+ALTER TABLE users ADD COLUMN password_login_enabled INTEGER NOT NULL DEFAULT TRUE;
+
+CREATE TABLE credentials (
+    id           TEXT PRIMARY KEY NOT NULL,
+    username     TEXT REFERENCES users(username) ON DELETE CASCADE NOT NULL,
+    name         TEXT NOT NULL,
+    public_key   BLOB NOT NULL,
+    sign_count   INTEGER NOT NULL DEFAULT 0,
+    created_at   TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TEXT
+);
+
+CREATE TABLE sessions (
+    token_hash TEXT PRIMARY KEY NOT NULL,
+    username   TEXT REFERENCES users(username) ON DELETE CASCADE NOT NULL,
+    expires_at INTEGER NOT NULL
+);
@@ -0,0 +1,2 @@
+-- JSON array of the widths that display sized copies were made at
+ALTER TABLE images ADD COLUMN display_widths TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,2 @@
+-- Format sniffed from the upload, e.g. jpeg or tiff. Unknown for older images.
+ALTER TABLE images ADD COLUMN format TEXT;
@@ -0,0 +1,2 @@
+-- Images the admin has chosen to show without the watermark
+ALTER TABLE images ADD COLUMN watermark_exempt INTEGER NOT NULL DEFAULT FALSE;
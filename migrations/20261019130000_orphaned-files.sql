@@ -0,0 +1,8 @@
+-- Files of deleted or replaced images waiting to be removed from disk. They're
+-- queued in the same transaction that stops the images table using them, and
+-- forgotten once they're gone.
+CREATE TABLE orphaned_files (
+    id       INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    kind     TEXT NOT NULL,
+    filename TEXT NOT NULL
+);
@@ -0,0 +1,13 @@
+-- Image processing waiting to be done in the background. `task` is JSON
+-- describing the work, jobs are removed once done. Failed jobs are retried
+-- after `run_after`, a unix timestamp, until they run out of attempts and are
+-- left as failed for an admin to retry.
+CREATE TABLE jobs (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    image_id   INTEGER NOT NULL REFERENCES images(id) ON DELETE CASCADE,
+    task       TEXT NOT NULL,
+    status     TEXT NOT NULL DEFAULT 'pending',
+    attempts   INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    run_after  INTEGER NOT NULL DEFAULT 0
+);
@@ -0,0 +1,11 @@
+-- The part of an image each thumbnail profile was cropped to, in pixels of
+-- the original, so thumbnails can be made again without losing it
+CREATE TABLE thumbnail_crops (
+    image_id INTEGER NOT NULL REFERENCES images(id) ON DELETE CASCADE,
+    profile  TEXT NOT NULL,
+    x        REAL NOT NULL,
+    y        REAL NOT NULL,
+    width    REAL NOT NULL,
+    height   REAL NOT NULL,
+    PRIMARY KEY (image_id, profile)
+);
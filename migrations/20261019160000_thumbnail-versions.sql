@@ -0,0 +1,3 @@
+-- A hash of each thumbnail profile's current file, by profile name, added to
+-- thumbnail URLs so a new crop gets a new URL
+ALTER TABLE images ADD COLUMN thumbnail_versions TEXT NOT NULL DEFAULT '{}';
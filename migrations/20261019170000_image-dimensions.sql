@@ -0,0 +1,11 @@
+-- Recorded as an image's files are made, so pages can reserve the space for
+-- images and show something while they load. The thumbnail columns are of
+-- the default profile's thumbnail.
+ALTER TABLE images ADD COLUMN width INTEGER;
+ALTER TABLE images ADD COLUMN height INTEGER;
+ALTER TABLE images ADD COLUMN thumb_width INTEGER;
+ALTER TABLE images ADD COLUMN thumb_height INTEGER;
+-- As #rrggbb
+ALTER TABLE images ADD COLUMN dominant_color TEXT;
+-- A tiny copy of the thumbnail as a data URI
+ALTER TABLE images ADD COLUMN placeholder TEXT;
@@ -0,0 +1,12 @@
+-- Images are only listed once their default thumbnail has a version. Ones
+-- whose thumbnail was made before versions were recorded get a placeholder
+-- version until it's next made.
+UPDATE images
+SET thumbnail_versions = json_set(thumbnail_versions, '$.default', 'legacy')
+WHERE json_extract(thumbnail_versions, '$.default') IS NULL
+  AND NOT EXISTS (
+    SELECT 1 FROM jobs
+    WHERE jobs.image_id = images.id
+      AND json_extract(jobs.task, '$.kind') = 'thumbnail'
+      AND json_extract(jobs.task, '$.profile') = 'default'
+  );
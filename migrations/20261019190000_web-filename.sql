@@ -0,0 +1,4 @@
+-- The name thumbnails and display copies are saved under, with the extension
+-- of the format they're saved in. Unknown for older images, whose copies share
+-- the original's name.
+ALTER TABLE images ADD COLUMN web_filename TEXT;
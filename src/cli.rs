@@ -1,6 +1,15 @@
-use std::path::PathBuf;
+use std::{net::SocketAddr, path::PathBuf};
 
 use clap::Parser;
+use ipnet::IpNet;
+
+use crate::services::{
+    ip_allowlist::parse_network,
+    metadata::MetadataPolicy,
+    security_headers::{DEFAULT_ADMIN_CSP, DEFAULT_PUBLIC_CSP},
+    thumbs::{parse_thumbnail_profile, ThumbnailProfile},
+    watermark::WatermarkPosition,
+};
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -8,4 +17,76 @@ use clap::Parser;
 pub struct Cli {
     #[clap(long)]
     pub root_dir: PathBuf,
+    /// Address the public site is served on
+    #[clap(long, default_value = "127.0.0.1:3000")]
+    pub bind: SocketAddr,
+    /// Serve the admin pages on this address instead of alongside the public site
+    #[clap(long)]
+    pub admin_bind: Option<SocketAddr>,
+    /// Only allow admin requests from these comma separated addresses or CIDR networks
+    #[clap(long, value_delimiter = ',', value_parser = parse_network)]
+    pub admin_allow: Vec<IpNet>,
+    /// Content-Security-Policy sent with the public site
+    #[clap(long, default_value = DEFAULT_PUBLIC_CSP)]
+    pub public_csp: String,
+    /// Content-Security-Policy sent with the admin pages
+    #[clap(long, default_value = DEFAULT_ADMIN_CSP)]
+    pub admin_csp: String,
+    /// Strict-Transport-Security max-age in seconds for HTTPS requests, 0 disables it
+    #[clap(long, default_value_t = 31536000)]
+    pub hsts_max_age: u64,
+    /// Comma separated widths in pixels that images are scaled down to for display
+    #[clap(long, value_delimiter = ',', default_value = "800,1600,2400")]
+    pub display_widths: Vec<u32>,
+    /// Comma separated thumbnail profiles as name:size:aspect:fit, e.g.
+    /// square:400:1x1:cover. Size is the longest edge in pixels, aspect is
+    /// WxH or original and fit is contain or cover. A profile named default
+    /// replaces the 400px thumbnails served from /thumbs/{file}.
+    #[clap(
+        long,
+        value_delimiter = ',',
+        value_parser = parse_thumbnail_profile,
+        default_value = "square:400:1x1:cover,portrait:400:4x5:cover"
+    )]
+    pub thumbnail_profiles: Vec<ThumbnailProfile>,
+    /// Largest file that can be uploaded, in megabytes
+    #[clap(long, default_value_t = 100)]
+    pub max_upload_mb: u64,
+    /// Largest upload request in megabytes, covering every file and field in it
+    #[clap(long, default_value_t = 250)]
+    pub max_request_mb: u64,
+    /// Widest or tallest image that can be uploaded, in pixels
+    #[clap(long, default_value_t = 20000)]
+    pub max_image_dimension: u32,
+    /// Number of images processed at once in the background
+    #[clap(long, default_value_t = 2)]
+    pub image_workers: usize,
+    /// Metadata removed from uploaded originals before they are stored
+    #[clap(long, value_enum, default_value_t = MetadataPolicy::LocationAndCamera)]
+    pub metadata_policy: MetadataPolicy,
+    /// Text stamped on the display copies of images, drawn in --watermark-font.
+    /// While there is a watermark only admins can download originals.
+    #[clap(long, requires = "watermark_font", conflicts_with = "watermark_logo")]
+    pub watermark_text: Option<String>,
+    /// TrueType or OpenType font the watermark text is drawn in
+    #[clap(long)]
+    pub watermark_font: Option<PathBuf>,
+    /// PNG logo stamped on the display copies of images instead of text
+    #[clap(long)]
+    pub watermark_logo: Option<PathBuf>,
+    /// Corner of the image, or its center, the watermark is placed in
+    #[clap(long, value_enum, default_value_t = WatermarkPosition::BottomRight)]
+    pub watermark_position: WatermarkPosition,
+    /// Opacity of the watermark, from 0 to 1
+    #[clap(long, default_value_t = 0.5)]
+    pub watermark_opacity: f32,
+    /// Width of the watermark as a fraction of the image's width
+    #[clap(long, default_value_t = 0.25)]
+    pub watermark_scale: f32,
+    /// Domain passkeys are registered against, e.g. jinwonkim.art
+    #[clap(long, default_value = "localhost")]
+    pub webauthn_rp_id: String,
+    /// Origin the admin pages are served from, e.g. https://jinwonkim.art
+    #[clap(long, default_value = "http://localhost:3000")]
+    pub webauthn_origin: String,
 }
@@ -8,80 +8,61 @@ use tera::{Context, Tera};
 
 use crate::{
     model::forms::category::{CreateCategory, DeleteCategory, MoveCategory},
-    services::{
-        auth::{check_password_for_user, AuthBasic},
-        database::Database,
-    },
+    services::{auth::AdminUser, database::Database},
 };
 
 pub async fn get_admin_category_page(
-    AuthBasic((username, password)): AuthBasic,
+    _: AdminUser,
     Extension(tera): Extension<Tera>,
     Extension(db): Extension<Database>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if check_password_for_user(&username, &password, &db).await {
-        let mut ctx = Context::new();
+    let mut ctx = Context::new();
 
-        let categories = db.list_categories().await.map_err(|e| e.into())?;
+    let categories = db.list_categories().await.map_err(|e| e.into())?;
 
-        ctx.insert("current_page", "categories");
-        ctx.insert("categories", &categories);
-        ctx.insert(
-            "max_category_position",
-            &categories
-                .iter()
-                .max_by_key(|c| c.position)
-                .map(|c| c.position)
-                .unwrap_or(i64::MAX),
-        );
+    ctx.insert("current_page", "categories");
+    ctx.insert("categories", &categories);
+    ctx.insert(
+        "max_category_position",
+        &categories
+            .iter()
+            .max_by_key(|c| c.position)
+            .map(|c| c.position)
+            .unwrap_or(i64::MAX),
+    );
 
-        Ok(Html(tera.render("admin_categories.html", &ctx).unwrap()))
-    } else {
-        Err((StatusCode::UNAUTHORIZED, "Failed to check password".into()))
-    }
+    Ok(Html(tera.render("admin_categories.html", &ctx).unwrap()))
 }
 
 pub async fn post_category(
-    AuthBasic((username, password)): AuthBasic,
+    _: AdminUser,
     Form(payload): Form<CreateCategory>,
     Extension(db): Extension<Database>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if check_password_for_user(&username, &password, &db).await {
-        db.create_category(&payload.name)
-            .await
-            .map(|_| Redirect::to("/admin/categories"))
-            .map_err(|e| e.into())
-    } else {
-        Err((StatusCode::UNAUTHORIZED, "Failed to check password".into()))
-    }
+    db.create_category(&payload.name)
+        .await
+        .map(|_| Redirect::to("/admin/categories"))
+        .map_err(|e| e.into())
 }
 
 pub async fn move_category(
-    AuthBasic((username, password)): AuthBasic,
+    _: AdminUser,
     Form(payload): Form<MoveCategory>,
     Extension(db): Extension<Database>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if check_password_for_user(&username, &password, &db).await {
-        db.move_category(&payload.id, payload.up)
-            .await
-            .map(|_| Redirect::to("/admin/categories"))
-            .map_err(|e| e.into())
-    } else {
-        Err((StatusCode::UNAUTHORIZED, "Failed to check password".into()))
-    }
+    db.move_category(&payload.id, payload.up)
+        .await
+        .map(|_| Redirect::to("/admin/categories"))
+        .map_err(|e| e.into())
 }
 
 pub async fn delete_category(
-    AuthBasic((username, password)): AuthBasic,
+    _: AdminUser,
     Form(payload): Form<DeleteCategory>,
     Extension(db): Extension<Database>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if check_password_for_user(&username, &password, &db).await {
-        db.delete_category(&payload.id)
-            .await
-            .map(|_| Redirect::to("/admin"))
-            .map_err(|e| e.into())
-    } else {
-        Err((StatusCode::UNAUTHORIZED, "Failed to check password".into()))
-    }
+    db.delete_category(&payload.id)
+        .await
+        .map(|_| Redirect::to("/admin"))
+        .map_err(|e| e.into())
 }
@@ -8,72 +8,53 @@ use tera::{Context, Tera};
 
 use crate::{
     model::forms::faq::{CreateFaq, DeleteFaq, MoveFaq},
-    services::{
-        auth::{check_password_for_user, AuthBasic},
-        database::Database,
-    },
+    services::{auth::AdminUser, database::Database},
 };
 
 pub async fn get_admin_faq_page(
-    AuthBasic((username, password)): AuthBasic,
+    _: AdminUser,
     Extension(tera): Extension<Tera>,
     Extension(db): Extension<Database>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if check_password_for_user(&username, &password, &db).await {
-        let mut ctx = Context::new();
+    let mut ctx = Context::new();
 
-        let images = db.list_faqs().await.map_err(|e| e.into())?;
+    let images = db.list_faqs().await.map_err(|e| e.into())?;
 
-        ctx.insert("current_page", "faq");
-        ctx.insert("faqs", &images);
+    ctx.insert("current_page", "faq");
+    ctx.insert("faqs", &images);
 
-        Ok(Html(tera.render("admin_faq.html", &ctx).unwrap()))
-    } else {
-        Err((StatusCode::UNAUTHORIZED, "Failed to check password".into()))
-    }
+    Ok(Html(tera.render("admin_faq.html", &ctx).unwrap()))
 }
 
 pub async fn post_faq(
-    AuthBasic((username, password)): AuthBasic,
+    _: AdminUser,
     Form(payload): Form<CreateFaq>,
     Extension(db): Extension<Database>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if check_password_for_user(&username, &password, &db).await {
-        db.create_faq(payload)
-            .await
-            .map(|_| Redirect::to("/admin/faq"))
-            .map_err(|e| e.into())
-    } else {
-        Err((StatusCode::UNAUTHORIZED, "Failed to check password".into()))
-    }
+    db.create_faq(payload)
+        .await
+        .map(|_| Redirect::to("/admin/faq"))
+        .map_err(|e| e.into())
 }
 
 pub async fn move_faq(
-    AuthBasic((username, password)): AuthBasic,
+    _: AdminUser,
     Form(payload): Form<MoveFaq>,
     Extension(db): Extension<Database>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if check_password_for_user(&username, &password, &db).await {
-        db.move_faq(payload.id, payload.up)
-            .await
-            .map(|_| Redirect::to("/admin/faq"))
-            .map_err(|e| e.into())
-    } else {
-        Err((StatusCode::UNAUTHORIZED, "Failed to check password".into()))
-    }
+    db.move_faq(payload.id, payload.up)
+        .await
+        .map(|_| Redirect::to("/admin/faq"))
+        .map_err(|e| e.into())
 }
 
 pub async fn delete_faq(
-    AuthBasic((username, password)): AuthBasic,
+    _: AdminUser,
     Form(payload): Form<DeleteFaq>,
     Extension(db): Extension<Database>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if check_password_for_user(&username, &password, &db).await {
-        db.delete_faq(payload.id)
-            .await
-            .map(|_| Redirect::to("/admin/faq"))
-            .map_err(|e| e.into())
-    } else {
-        Err((StatusCode::UNAUTHORIZED, "Failed to check password".into()))
-    }
+    db.delete_faq(payload.id)
+        .await
+        .map(|_| Redirect::to("/admin/faq"))
+        .map_err(|e| e.into())
 }
@@ -1,280 +1,788 @@
 use axum::{
     extract::{Form, Multipart, Path},
-    http::StatusCode,
+    http::{header::CONTENT_TYPE, HeaderMap, StatusCode},
     response::{Html, IntoResponse, Redirect},
     Extension,
 };
+use image::ImageFormat;
 use tera::{Context, Tera};
 use uuid::Uuid;
 
 use crate::{
     model::{
         category::ImageCategory,
+        error::Error,
         forms::image::{
-            CreateImage, DeleteImage, HideImage, MoveImage, UpdateImage, UpdateThumbnailCrop,
+            BulkCreateImages, CreateImage, DeleteImage, ExemptImageFromWatermark, HideImage,
+            MoveImage, Rectangle, RegenerateDisplayVariants, ReplaceImage, RetryJob, UpdateImage,
+            UpdateThumbnailCrop,
         },
+        image::BulkUploadResult,
+        job::Task,
     },
     services::{
-        auth::{check_password_for_user, AuthBasic},
+        auth::AdminUser,
+        cleanup::{self, remove_orphaned_files},
         database::Database,
-        static_files::StaticFiles,
-        thumbs::make_thumbnail,
+        display::DisplayVariants,
+        formats::{self, sniff_upload_format},
+        jobs::JobQueue,
+        metadata::{self, MetadataPolicy},
+        static_files::{Staging, StaticFiles},
+        thumbs::{preview_thumbnail, suggest_thumbnail_crop, ThumbnailProfiles, DEFAULT_PROFILE},
+        uploads::UploadLimits,
     },
 };
 
 pub async fn get_admin_images_page(
-    AuthBasic((username, password)): AuthBasic,
+    _: AdminUser,
     Extension(tera): Extension<Tera>,
     Extension(db): Extension<Database>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if check_password_for_user(&username, &password, &db).await {
-        let mut ctx = Context::new();
-
-        let images = db.list_images().await.map_err(|e| e.into())?;
-        let categories = db.list_categories().await.map_err(|e| e.into())?;
-
-        ctx.insert("current_page", "images");
-        ctx.insert("categories", &categories);
-        ctx.insert("images", &images);
-        ctx.insert(
-            "max_image_position",
-            &images
-                .iter()
-                .max_by_key(|i| i.position)
-                .map(|i| i.position)
-                .unwrap_or(i64::MAX),
-        );
-
-        Ok(Html(tera.render("admin_images.html", &ctx).unwrap()))
-    } else {
-        Err((StatusCode::UNAUTHORIZED, "Failed to check password".into()))
-    }
+    let mut ctx = Context::new();
+
+    let images = db.list_images().await.map_err(|e| e.into())?;
+    let categories = db.list_categories().await.map_err(|e| e.into())?;
+    let jobs = db.list_jobs().await.map_err(|e| e.into())?;
+
+    ctx.insert("current_page", "images");
+    ctx.insert("categories", &categories);
+    ctx.insert("images", &images);
+    ctx.insert("jobs", &jobs);
+    ctx.insert(
+        "max_image_position",
+        &images
+            .iter()
+            .max_by_key(|i| i.position)
+            .map(|i| i.position)
+            .unwrap_or(i64::MAX),
+    );
+
+    Ok(Html(tera.render("admin_images.html", &ctx).unwrap()))
 }
 
 pub async fn get_admin_edit_image_page(
-    AuthBasic((username, password)): AuthBasic,
+    _: AdminUser,
     Path(image): Path<i64>,
     Extension(tera): Extension<Tera>,
+    Extension(display_variants): Extension<DisplayVariants>,
+    Extension(thumbnail_profiles): Extension<ThumbnailProfiles>,
     Extension(db): Extension<Database>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if check_password_for_user(&username, &password, &db).await {
-        let mut ctx = Context::new();
+    let mut ctx = Context::new();
 
-        let images = db.list_images().await.map_err(|e| e.into())?;
+    let images = db.list_images().await.map_err(|e| e.into())?;
 
-        let image = images
-            .iter()
-            .find(|i| i.id == image)
-            .ok_or((StatusCode::NOT_FOUND, "Image not found".to_owned()))?;
+    let image = images
+        .iter()
+        .find(|i| i.id == image)
+        .ok_or((StatusCode::NOT_FOUND, "Image not found".to_owned()))?;
 
-        let categories: Vec<ImageCategory> = db
-            .list_categories()
-            .await
-            .map_err(|e| e.into())?
-            .into_iter()
-            .map(|c| {
-                let checked = image.categories.iter().any(|ic| ic.id == c.id);
-                c.into_image_category(checked)
-            })
-            .collect();
+    let categories: Vec<ImageCategory> = db
+        .list_categories()
+        .await
+        .map_err(|e| e.into())?
+        .into_iter()
+        .map(|c| {
+            let checked = image.categories.iter().any(|ic| ic.id == c.id);
+            c.into_image_category(checked)
+        })
+        .collect();
 
-        ctx.insert("current_page", "images");
-        ctx.insert("categories", &categories);
-        ctx.insert("image", &image);
+    let jobs: Vec<_> = db
+        .list_jobs()
+        .await
+        .map_err(|e| e.into())?
+        .into_iter()
+        .filter(|j| j.image_id == image.id)
+        .collect();
 
-        Ok(Html(tera.render("admin_edit_image.html", &ctx).unwrap()))
-    } else {
-        Err((StatusCode::UNAUTHORIZED, "Failed to check password".into()))
-    }
+    let thumbnail_crops = db
+        .list_thumbnail_crops(image.id)
+        .await
+        .map_err(|e| e.into())?;
+
+    ctx.insert("current_page", "images");
+    ctx.insert("categories", &categories);
+    ctx.insert("image", &image);
+    ctx.insert("jobs", &jobs);
+    ctx.insert("thumbnail_crops", &thumbnail_crops);
+    ctx.insert("watermarked", &display_variants.is_watermarked());
+    ctx.insert(
+        "thumbnail_profiles",
+        &thumbnail_profiles
+            .named()
+            .map(|p| &p.name)
+            .collect::<Vec<_>>(),
+    );
+
+    Ok(Html(tera.render("admin_edit_image.html", &ctx).unwrap()))
 }
 
 pub async fn get_admin_edit_thumbnail_page(
-    AuthBasic((username, password)): AuthBasic,
+    admin: AdminUser,
     Path(image): Path<i64>,
+    tera: Extension<Tera>,
+    static_files: Extension<StaticFiles>,
+    thumbnail_profiles: Extension<ThumbnailProfiles>,
+    db: Extension<Database>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    get_admin_edit_profile_thumbnail_page(
+        admin,
+        Path((image, DEFAULT_PROFILE.to_owned())),
+        tera,
+        static_files,
+        thumbnail_profiles,
+        db,
+    )
+    .await
+}
+
+pub async fn get_admin_edit_profile_thumbnail_page(
+    _: AdminUser,
+    Path((image, profile)): Path<(i64, String)>,
     Extension(tera): Extension<Tera>,
+    Extension(static_files): Extension<StaticFiles>,
+    Extension(thumbnail_profiles): Extension<ThumbnailProfiles>,
     Extension(db): Extension<Database>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if check_password_for_user(&username, &password, &db).await {
-        let mut ctx = Context::new();
+    let mut ctx = Context::new();
 
-        // TODO seems to be using old method - listing all images then filtering
-        // in application code seems silly.
-        let images = db.list_images().await.map_err(|e| e.into())?;
+    // TODO seems to be using old method - listing all images then filtering
+    // in application code seems silly.
+    let images = db.list_images().await.map_err(|e| e.into())?;
 
-        let image = images
-            .iter()
-            .find(|i| i.id == image)
-            .ok_or((StatusCode::NOT_FOUND, "Image not found".to_owned()))?;
+    let image = images
+        .iter()
+        .find(|i| i.id == image)
+        .ok_or((StatusCode::NOT_FOUND, "Image not found".to_owned()))?;
 
-        ctx.insert("current_page", "images");
-        ctx.insert("image", &image);
+    let profile = thumbnail_profiles.get(&profile).ok_or((
+        StatusCode::NOT_FOUND,
+        "Thumbnail profile not found".to_owned(),
+    ))?;
 
-        Ok(Html(
-            tera.render("admin_edit_image_thumbnail_crop.html", &ctx)
-                .unwrap(),
-        ))
-    } else {
-        Err((StatusCode::UNAUTHORIZED, "Failed to check password".into()))
-    }
+    let saved_crop = db
+        .get_thumbnail_crop(image.id, &profile.name)
+        .await
+        .map_err(|e| e.into())?;
+
+    // Square profiles start from the crop they'd be made with otherwise
+    let suggested_crop = match saved_crop {
+        Some(_) => None,
+        None => {
+            let filename = image.filename.clone();
+            let profile = profile.clone();
+            let suggested = tokio::task::spawn_blocking(move || {
+                suggest_thumbnail_crop(&filename, &profile, &static_files)
+            })
+            .await;
+
+            match suggested.map_err(anyhow::Error::from).and_then(|s| s) {
+                Ok(crop) => crop,
+                Err(e) => {
+                    tracing::warn!("Failed to suggest a crop for {}: {}", image.filename, e);
+                    None
+                }
+            }
+        }
+    };
+
+    // Crops have always been square, unless the profile has a shape of its own
+    let aspect_ratio = profile
+        .aspect
+        .map(|(width, height)| width as f64 / height as f64)
+        .unwrap_or(1.0);
+
+    ctx.insert("current_page", "images");
+    ctx.insert("image", &image);
+    ctx.insert("profile", &profile.name);
+    ctx.insert("aspect_ratio", &aspect_ratio);
+    ctx.insert("suggested", &suggested_crop.is_some());
+    ctx.insert("crop", &saved_crop.or(suggested_crop));
+
+    Ok(Html(
+        tera.render("admin_edit_image_thumbnail_crop.html", &ctx)
+            .unwrap(),
+    ))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn post_image(
+    _: AdminUser,
+    headers: HeaderMap,
     payload: Multipart,
-    AuthBasic((username, password)): AuthBasic,
     Extension(static_files): Extension<StaticFiles>,
+    Extension(metadata_policy): Extension<MetadataPolicy>,
+    Extension(upload_limits): Extension<UploadLimits>,
+    Extension(jobs): Extension<JobQueue>,
     Extension(db): Extension<Database>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if check_password_for_user(&username, &password, &db).await {
-        let image_upload = CreateImage::from_multipart(payload)
-            .await
-            .map_err(|e| e.into())?;
+    let mut budget = upload_limits.budget(&headers).map_err(|e| e.into())?;
+
+    // Nothing is put in place until the row is being saved, so a failure at
+    // any step leaves no trace once the staged files are discarded.
+    let mut staging = static_files.stage().await.map_err(|e| e.into())?;
+
+    let created = async {
+        let image_upload =
+            CreateImage::from_multipart(payload, &mut budget, &staging.upload_path())
+                .await
+                .map_err(|e| e.into())?;
+
+        save_new_image(
+            image_upload,
+            &mut staging,
+            &static_files,
+            &upload_limits,
+            metadata_policy,
+            &jobs,
+            &db,
+        )
+        .await
+    }
+    .await;
+
+    staging.discard().await;
 
-        let uploaded_ext = image_upload.img_name.to_lowercase();
+    created.map(|_| Redirect::to("/admin/images"))
+}
+
+// Each file is saved on its own, so one bad file doesn't stop the rest
+#[allow(clippy::too_many_arguments)]
+pub async fn post_bulk_images(
+    _: AdminUser,
+    headers: HeaderMap,
+    payload: Multipart,
+    Extension(tera): Extension<Tera>,
+    Extension(static_files): Extension<StaticFiles>,
+    Extension(metadata_policy): Extension<MetadataPolicy>,
+    Extension(upload_limits): Extension<UploadLimits>,
+    Extension(jobs): Extension<JobQueue>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let mut budget = upload_limits.budget(&headers).map_err(|e| e.into())?;
 
-        let ext = if uploaded_ext.ends_with("png") {
-            ".png"
-        } else if uploaded_ext.ends_with("jpg") || uploaded_ext.ends_with("jpeg") {
-            ".jpg"
-        } else {
-            ""
+    let upload = BulkCreateImages::from_multipart(payload, &mut budget, &static_files)
+        .await
+        .map_err(|e| e.into())?;
+
+    let mut results = vec![];
+    for (i, img) in upload.imgs.into_iter().enumerate() {
+        let name = upload
+            .names
+            .get(i)
+            .map(|name| name.trim())
+            .filter(|name| !name.is_empty())
+            .map(str::to_owned)
+            .unwrap_or_else(|| name_from_filename(&img.img_name));
+
+        let mut staging = img.staging;
+        let created = match img.error {
+            Some(e) => Err(e.into()),
+            None => {
+                let image_upload = CreateImage {
+                    name: name.clone(),
+                    description: upload.description.clone(),
+                    categories: upload.categories.clone(),
+                    img_name: img.img_name.clone(),
+                    thumbnail_crop_rect: None,
+                };
+
+                save_new_image(
+                    image_upload,
+                    &mut staging,
+                    &static_files,
+                    &upload_limits,
+                    metadata_policy,
+                    &jobs,
+                    &db,
+                )
+                .await
+            }
         };
+        staging.discard().await;
 
-        let mut filename = Uuid::new_v4().to_string();
-        filename.push_str(ext);
+        if let Err((_, e)) = &created {
+            tracing::warn!("Bulk upload of {} failed: {}", img.img_name, e);
+        }
 
-        static_files
-            .save_image(&filename, &image_upload.img)
-            .await
-            .map_err(|e| {
-                tracing::error!("Error while saving image: {}", e);
-                (StatusCode::BAD_REQUEST, e.to_string())
-            })?;
+        results.push(BulkUploadResult {
+            name,
+            img_name: img.img_name,
+            error: created.err().map(|(_, e)| e),
+        });
+    }
 
-        make_thumbnail(&filename, image_upload.thumbnail_crop_rect, &static_files)
-            .await
-            .map_err(|e| {
-                tracing::error!("Error while creating thumbnail: {}", e);
-                // TODO attempt to clean up saved image
-                (StatusCode::BAD_REQUEST, e.to_string())
-            })?;
+    let mut ctx = Context::new();
+    ctx.insert("current_page", "images");
+    ctx.insert("results", &results);
+
+    Ok(Html(tera.render("admin_bulk_upload.html", &ctx).unwrap()))
+}
 
-        db.create_image(
+// `Sunset (final).jpg` is named `Sunset (final)`
+fn name_from_filename(filename: &str) -> String {
+    std::path::Path::new(filename)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(filename)
+        .to_owned()
+}
+
+// Saves the original from the upload in the staging area along with the jobs
+// that make its thumbnail and display copies, undoing any files put in place
+// if the row couldn't be saved
+async fn save_new_image(
+    image_upload: CreateImage,
+    staging: &mut Staging,
+    static_files: &StaticFiles,
+    upload_limits: &UploadLimits,
+    metadata_policy: MetadataPolicy,
+    jobs: &JobQueue,
+    db: &Database,
+) -> Result<(), (StatusCode, String)> {
+    let staged = stage_image(
+        &image_upload.img_name,
+        staging,
+        upload_limits,
+        metadata_policy,
+    )
+    .await?;
+
+    if let Some(rect) = &image_upload.thumbnail_crop_rect {
+        let size = image_size(&staged.filename, staging.files()).await?;
+        check_crop_rect(rect, size)?;
+    }
+
+    let created = db
+        .create_image(
             image_upload.name,
             image_upload.description,
-            filename,
+            staged.filename,
+            &staged.web_filename,
             image_upload.categories,
+            formats::name(staged.format),
+            image_upload.thumbnail_crop_rect,
+            &jobs.new_image_tasks(),
+            staging.publish(static_files),
         )
-        .await
-        .map(|_| Redirect::to("/admin/images"))
-        .map_err(|e| {
-            // TODO attempt to clean up image and thumbnail
-            e.into()
-        })
-    } else {
-        Err((StatusCode::UNAUTHORIZED, "Failed to check password".into()))
+        .await;
+
+    if let Err(e) = created {
+        staging.unpublish().await;
+        return Err(e.into());
     }
+
+    jobs.wake();
+
+    Ok(())
 }
 
 pub async fn put_image(
+    _: AdminUser,
+    headers: HeaderMap,
+    payload: Multipart,
+    Extension(upload_limits): Extension<UploadLimits>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let mut budget = upload_limits.budget(&headers).map_err(|e| e.into())?;
+
+    let image_update = UpdateImage::from_multipart(payload, &mut budget)
+        .await
+        .map_err(|e| e.into())?;
+
+    db.update_image(
+        image_update.id,
+        image_update.name,
+        image_update.description,
+        image_update.categories,
+    )
+    .await
+    .map(|_| Redirect::to("/admin/images"))
+    .map_err(|e| e.into())
+}
+
+// For a rescan, which shouldn't lose the image's URL, position or categories.
+// The new file is saved under a new name so nothing cached of the old one is
+// shown in its place.
+#[allow(clippy::too_many_arguments)]
+pub async fn post_replace_image(
+    _: AdminUser,
+    headers: HeaderMap,
     payload: Multipart,
-    AuthBasic((username, password)): AuthBasic,
+    Extension(static_files): Extension<StaticFiles>,
+    Extension(metadata_policy): Extension<MetadataPolicy>,
+    Extension(upload_limits): Extension<UploadLimits>,
+    Extension(jobs): Extension<JobQueue>,
     Extension(db): Extension<Database>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if check_password_for_user(&username, &password, &db).await {
-        let image_update = UpdateImage::from_multipart(payload)
+    let mut budget = upload_limits.budget(&headers).map_err(|e| e.into())?;
+
+    let mut staging = static_files.stage().await.map_err(|e| e.into())?;
+
+    let replaced = async {
+        let replacement =
+            ReplaceImage::from_multipart(payload, &mut budget, &staging.upload_path())
+                .await
+                .map_err(|e| e.into())?;
+
+        let image = db
+            .get_image_by_id(replacement.id)
             .await
-            .map_err(|e| e.into())?;
+            .map_err(|e| e.into())?
+            .ok_or((StatusCode::NOT_FOUND, "Image not found".to_owned()))?;
 
-        db.update_image(
-            image_update.id,
-            image_update.name,
-            image_update.description,
-            image_update.categories,
+        let staged = stage_image(
+            &replacement.img_name,
+            &staging,
+            &upload_limits,
+            metadata_policy,
+        )
+        .await?;
+
+        // The old crop was of the old scan, so the new one starts uncropped
+        db.replace_image_file(
+            image.id,
+            &staged.filename,
+            &staged.web_filename,
+            formats::name(staged.format),
+            &jobs.new_image_tasks(),
+            &cleanup::image_files(&image),
+            staging.publish(&static_files),
         )
         .await
-        .map(|_| Redirect::to("/admin/images"))
-        .map_err(|e| e.into())
-    } else {
-        Err((StatusCode::UNAUTHORIZED, "Failed to check password".into()))
+        .map_err(|e| e.into())?;
+
+        Ok(image.id)
+    }
+    .await;
+
+    if replaced.is_err() {
+        staging.unpublish().await;
+    }
+    staging.discard().await;
+    let id = replaced?;
+
+    jobs.wake();
+
+    // The record has moved on, so anything left behind is retried later
+    if let Err(e) = remove_orphaned_files(&db, &static_files).await {
+        tracing::warn!("Failed to remove old image files: {}", e);
     }
+
+    Ok(Redirect::to(&format!("/admin/images/edit/{}", id)))
+}
+
+struct StagedImage {
+    filename: String,
+    web_filename: String,
+    format: ImageFormat,
+}
+
+// Checks the file that was uploaded to the staging area, then writes the
+// original there too
+async fn stage_image(
+    upload_name: &str,
+    staging: &Staging,
+    upload_limits: &UploadLimits,
+    metadata_policy: MetadataPolicy,
+) -> Result<StagedImage, (StatusCode, String)> {
+    let upload_path = staging.upload_path();
+
+    // Reading the header of a large TIFF or a corrupt file can take a while
+    let checked = {
+        let upload_path = upload_path.clone();
+        let upload_limits = *upload_limits;
+        tokio::task::spawn_blocking(move || {
+            let format = sniff_upload_format(&upload_path)?;
+            upload_limits.check_dimensions(&upload_path)?;
+            let color = formats::color_type(&upload_path, format)?;
+            Ok::<_, Error>((format, formats::web_format(format, color)))
+        })
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    };
+
+    let (format, web_format) = checked.map_err(|e| {
+        tracing::warn!("Rejected upload {}: {}", upload_name, e);
+        e.into()
+    })?;
+
+    let filename = format!("{}.{}", Uuid::new_v4(), formats::extension(format));
+    let web_filename = formats::web_filename(&filename, web_format);
+    let image_path = staging
+        .files()
+        .new_image_path(&filename)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    // Copied a segment at a time, uploads can be up to the request size limit
+    tokio::task::spawn_blocking(move || {
+        metadata::strip(&upload_path, &image_path, format, metadata_policy)
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .map_err(|e| {
+        tracing::error!("Error while saving image: {}", e);
+        e.into()
+    })?;
+
+    Ok(StagedImage {
+        filename,
+        web_filename,
+        format,
+    })
 }
 
 pub async fn move_image(
-    AuthBasic((username, password)): AuthBasic,
+    _: AdminUser,
     Form(payload): Form<MoveImage>,
     Extension(db): Extension<Database>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if check_password_for_user(&username, &password, &db).await {
-        db.move_image(payload.id, payload.up)
-            .await
-            .map(|_| Redirect::to("/admin/images"))
-            .map_err(|e| e.into())
-    } else {
-        Err((StatusCode::UNAUTHORIZED, "Failed to check password".into()))
-    }
+    db.move_image(payload.id, payload.up)
+        .await
+        .map(|_| Redirect::to("/admin/images"))
+        .map_err(|e| e.into())
 }
 
 pub async fn hide_image(
-    AuthBasic((username, password)): AuthBasic,
+    _: AdminUser,
     Form(payload): Form<HideImage>,
     Extension(db): Extension<Database>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if check_password_for_user(&username, &password, &db).await {
-        db.hide_image(payload.id, payload.hide)
-            .await
-            .map(|_| Redirect::to("/admin/images"))
-            .map_err(|e| e.into())
-    } else {
-        Err((StatusCode::UNAUTHORIZED, "Failed to check password".into()))
-    }
+    db.hide_image(payload.id, payload.hide)
+        .await
+        .map(|_| Redirect::to("/admin/images"))
+        .map_err(|e| e.into())
+}
+
+pub async fn exempt_image_from_watermark(
+    _: AdminUser,
+    Form(payload): Form<ExemptImageFromWatermark>,
+    Extension(jobs): Extension<JobQueue>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    db.set_watermark_exempt(payload.id, payload.exempt)
+        .await
+        .map_err(|e| e.into())?;
+
+    jobs.enqueue(payload.id, &[Task::DisplayVariants])
+        .await
+        .map_err(|e| e.into())?;
+
+    Ok(Redirect::to(&format!("/admin/images/edit/{}", payload.id)))
 }
 
 pub async fn delete_image(
-    AuthBasic((username, password)): AuthBasic,
+    _: AdminUser,
     Form(payload): Form<DeleteImage>,
+    Extension(static_files): Extension<StaticFiles>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let image = db
+        .get_image_by_id(payload.id)
+        .await
+        .map_err(|e| e.into())?
+        .ok_or((StatusCode::NOT_FOUND, "Image not found".to_owned()))?;
+
+    db.delete_image(image.id, &cleanup::image_files(&image))
+        .await
+        .map_err(|e| e.into())?;
+
+    // The image is gone either way, anything left behind is retried later
+    if let Err(e) = remove_orphaned_files(&db, &static_files).await {
+        tracing::warn!("Failed to remove deleted image files: {}", e);
+    }
+
+    Ok(Redirect::to("/admin/images"))
+}
+
+// For after the display widths have been changed, and to make the modern format
+// copies of images uploaded before they existed
+pub async fn post_regenerate_display_variants(
+    _: AdminUser,
+    Form(payload): Form<RegenerateDisplayVariants>,
+    Extension(jobs): Extension<JobQueue>,
     Extension(db): Extension<Database>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if check_password_for_user(&username, &password, &db).await {
-        db.delete_image(payload.id)
+    let images = match payload.id {
+        Some(id) => db
+            .get_image_by_id(id)
             .await
-            .map(|_| Redirect::to("/admin/images"))
-            .map_err(|e| e.into())
-    } else {
-        Err((StatusCode::UNAUTHORIZED, "Failed to check password".into()))
+            .map_err(|e| e.into())?
+            .into_iter()
+            .collect(),
+        None => db.list_images().await.map_err(|e| e.into())?,
+    };
+
+    for image in images {
+        let has_default_crop = db
+            .get_thumbnail_crop(image.id, DEFAULT_PROFILE)
+            .await
+            .map_err(|e| e.into())?
+            .is_some();
+
+        jobs.enqueue(image.id, &jobs.regenerate_tasks(has_default_crop))
+            .await
+            .map_err(|e| e.into())?;
     }
+
+    let redirect_path = match payload.id {
+        Some(id) => format!("/admin/images/edit/{}", id),
+        None => "/admin/images".to_string(),
+    };
+
+    Ok(Redirect::to(&redirect_path))
 }
 
 pub async fn post_update_thumbnail_crop(
-    AuthBasic((username, password)): AuthBasic,
+    _: AdminUser,
     Form(payload): Form<UpdateThumbnailCrop>,
     Extension(static_files): Extension<StaticFiles>,
+    Extension(thumbnail_profiles): Extension<ThumbnailProfiles>,
+    Extension(jobs): Extension<JobQueue>,
     Extension(db): Extension<Database>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if check_password_for_user(&username, &password, &db).await {
-        let Some(image) = db.get_image_by_id(payload.id).await.expect("fml") else {
-            return Err((StatusCode::NOT_FOUND, "Image not found".to_string()));
-        };
+    let Some(image) = db.get_image_by_id(payload.id).await.expect("fml") else {
+        return Err((StatusCode::NOT_FOUND, "Image not found".to_string()));
+    };
+
+    let size = image_size(&image.filename, &static_files).await?;
+    let rect = parse_crop_rect(&payload.thumbnail_crop_rect, size)?;
+
+    let profile = payload.profile.as_deref().unwrap_or(DEFAULT_PROFILE);
+    if thumbnail_profiles.get(profile).is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Unknown thumbnail profile: {}", profile),
+        ));
+    }
 
-        let rect = serde_json::from_str(&payload.thumbnail_crop_rect).map_err(|_| {
-            (
-                StatusCode::BAD_REQUEST,
-                format!("Invalid rectangle JSON: {}", payload.thumbnail_crop_rect),
-            )
-        })?;
+    // Saved first, so the job and any later regeneration use it
+    db.set_thumbnail_crop(image.id, profile, &rect)
+        .await
+        .map_err(|e| e.into())?;
 
-        make_thumbnail(&image.filename, Some(rect), &static_files)
-            .await
-            .map_err(|e| {
-                tracing::error!("Error while creating thumbnail: {}", e);
-                // TODO attempt to clean up saved image
-                (StatusCode::BAD_REQUEST, e.to_string())
-            })?;
+    jobs.enqueue(
+        image.id,
+        &[Task::Thumbnail {
+            profile: profile.to_owned(),
+        }],
+    )
+    .await
+    .map_err(|e| e.into())?;
+
+    let mut redirect_path = "/admin/images/edit/".to_string();
+    redirect_path.push_str(&payload.id.to_string());
+
+    Ok(Redirect::to(&redirect_path))
+}
+
+/// Responds with the thumbnail a crop would make, without saving the crop or
+/// replacing the thumbnail, so crops can be compared before choosing one
+pub async fn post_preview_thumbnail_crop(
+    _: AdminUser,
+    Form(payload): Form<UpdateThumbnailCrop>,
+    Extension(static_files): Extension<StaticFiles>,
+    Extension(thumbnail_profiles): Extension<ThumbnailProfiles>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let Some(image) = db.get_image_by_id(payload.id).await.map_err(|e| e.into())? else {
+        return Err((StatusCode::NOT_FOUND, "Image not found".to_string()));
+    };
+
+    let size = image_size(&image.filename, &static_files).await?;
+    let rect = parse_crop_rect(&payload.thumbnail_crop_rect, size)?;
+
+    let profile_name = payload.profile.as_deref().unwrap_or(DEFAULT_PROFILE);
+    let profile = thumbnail_profiles.get(profile_name).cloned().ok_or((
+        StatusCode::BAD_REQUEST,
+        format!("Unknown thumbnail profile: {}", profile_name),
+    ))?;
+
+    let (bytes, format) = tokio::task::spawn_blocking(move || {
+        preview_thumbnail(
+            &image.filename,
+            &image.web_filename,
+            &profile,
+            rect,
+            &static_files,
+        )
+    })
+    .await
+    .map_err(anyhow::Error::from)
+    .and_then(|preview| preview)
+    .map_err(|e| {
+        tracing::error!("Failed to preview thumbnail crop: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to preview thumbnail".to_owned(),
+        )
+    })?;
+
+    Ok(([(CONTENT_TYPE, formats::content_type(format))], bytes))
+}
+
+// Cropper gives the rectangle as JSON, in the original image's pixels
+fn parse_crop_rect(json: &str, size: (u32, u32)) -> Result<Rectangle, (StatusCode, String)> {
+    let rect: Rectangle = serde_json::from_str(json).map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("Invalid rectangle JSON: {}", json),
+        )
+    })?;
+
+    check_crop_rect(&rect, size)?;
 
-        let mut redirect_path = "/admin/images/edit/".to_string();
-        redirect_path.push_str(&payload.id.to_string());
+    Ok(rect)
+}
 
-        Ok(Redirect::to(&redirect_path))
+// The crop is measured on the image turned the way it's shown, and has to be
+// at least a pixel inside it. Fractions are dropped when cropping, so they
+// can't take it past the edge.
+fn check_crop_rect(
+    rect: &Rectangle,
+    (width, height): (u32, u32),
+) -> Result<(), (StatusCode, String)> {
+    let inside = |start: f64, len: f64, max: u32| {
+        start.is_finite()
+            && len.is_finite()
+            && start >= 0.0
+            && len >= 1.0
+            && start as u64 + len as u64 <= max as u64
+    };
+
+    if inside(rect.x, rect.width, width) && inside(rect.y, rect.height, height) {
+        Ok(())
     } else {
-        Err((StatusCode::UNAUTHORIZED, "Failed to check password".into()))
+        Err((
+            StatusCode::BAD_REQUEST,
+            format!("The crop isn't inside the {}x{} image", width, height),
+        ))
     }
 }
+
+async fn image_size(
+    filename: &str,
+    static_files: &StaticFiles,
+) -> Result<(u32, u32), (StatusCode, String)> {
+    let path = static_files
+        .get_image_path(filename)
+        .map_err(|e| e.into())?;
+
+    tokio::task::spawn_blocking(move || formats::dimensions(&path))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| e.into())
+}
+
+pub async fn post_retry_job(
+    _: AdminUser,
+    Form(payload): Form<RetryJob>,
+    Extension(jobs): Extension<JobQueue>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    jobs.retry(payload.id)
+        .await
+        .map(|_| Redirect::to("/admin/images"))
+        .map_err(|e| e.into())
+}
@@ -0,0 +1,249 @@
+use axum::{
+    extract::{Form, Json},
+    http::{header::SET_COOKIE, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect},
+    Extension,
+};
+use serde_json::json;
+use tera::{Context, Tera};
+
+use crate::{
+    model::forms::passkey::{
+        DeletePasskey, FinishPasskeyLogin, RegisterPasskey, SetPasswordLogin, StartPasskeyLogin,
+    },
+    services::{
+        auth::{
+            generate_session_token, hash_session_token, now, session_token, AdminUser,
+            SESSION_COOKIE, SESSION_LENGTH,
+        },
+        database::Database,
+        webauthn::Webauthn,
+    },
+};
+
+pub async fn get_admin_login_page(
+    Extension(tera): Extension<Tera>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let mut ctx = Context::new();
+
+    ctx.insert("current_page", "login");
+
+    Ok(Html(tera.render("admin_login.html", &ctx).unwrap()))
+}
+
+pub async fn post_passkey_login_start(
+    Extension(db): Extension<Database>,
+    Extension(webauthn): Extension<Webauthn>,
+    Json(payload): Json<StartPasskeyLogin>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let username = payload
+        .username
+        .as_deref()
+        .map(str::trim)
+        .filter(|u| !u.is_empty());
+
+    // Without a username the authenticator offers any discoverable passkey it
+    // holds for this site.
+    let credentials = match username {
+        Some(username) => db
+            .list_credentials_for_user(username)
+            .await
+            .map_err(|e| e.into())?,
+        None => vec![],
+    };
+
+    Ok(Json(webauthn.start_authentication(username, &credentials)))
+}
+
+pub async fn post_passkey_login_finish(
+    Extension(db): Extension<Database>,
+    Extension(webauthn): Extension<Webauthn>,
+    Json(payload): Json<FinishPasskeyLogin>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let credential = db
+        .get_credential(&payload.id)
+        .await
+        .map_err(|e| e.into())?
+        .ok_or((StatusCode::UNAUTHORIZED, "Unknown passkey".to_string()))?;
+
+    let sign_count = webauthn
+        .finish_authentication(&payload, &credential)
+        .map_err(|e| {
+            tracing::warn!("Passkey login failed for {}: {}", credential.username, e);
+            (StatusCode::UNAUTHORIZED, e.to_string())
+        })?;
+
+    db.update_credential_sign_count(&credential.id, sign_count as i64)
+        .await
+        .map_err(|e| e.into())?;
+
+    let token = generate_session_token();
+    let now = now();
+    let expires_at = now + SESSION_LENGTH.as_secs() as i64;
+
+    db.create_session(
+        &hash_session_token(&token),
+        &credential.username,
+        expires_at,
+        now,
+    )
+    .await
+    .map_err(|e| e.into())?;
+
+    tracing::info!("User {} logged in with a passkey", credential.username);
+
+    let cookie = format!(
+        "{}={}; Path=/admin; Max-Age={}; HttpOnly; SameSite=Strict{}",
+        SESSION_COOKIE,
+        token,
+        SESSION_LENGTH.as_secs(),
+        if webauthn.is_secure_origin() {
+            "; Secure"
+        } else {
+            ""
+        }
+    );
+
+    Ok((
+        [(SET_COOKIE, cookie)],
+        Json(json!({ "redirect": "/admin" })),
+    ))
+}
+
+pub async fn post_logout(
+    headers: HeaderMap,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    if let Some(token) = session_token(&headers) {
+        db.delete_session(&hash_session_token(token))
+            .await
+            .map_err(|e| e.into())?;
+    }
+
+    let cookie = format!(
+        "{}=; Path=/admin; Max-Age=0; HttpOnly; SameSite=Strict",
+        SESSION_COOKIE
+    );
+
+    Ok(([(SET_COOKIE, cookie)], Redirect::to("/admin/login")))
+}
+
+pub async fn get_admin_passkeys_page(
+    AdminUser(username): AdminUser,
+    Extension(tera): Extension<Tera>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let mut ctx = Context::new();
+
+    let user = db
+        .get_user(&username)
+        .await
+        .map_err(|e| e.into())?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".to_owned()))?;
+    let credentials = db
+        .list_credentials_for_user(&username)
+        .await
+        .map_err(|e| e.into())?;
+
+    ctx.insert("current_page", "passkeys");
+    ctx.insert("username", &username);
+    ctx.insert("credentials", &credentials);
+    ctx.insert("password_login_enabled", &user.password_login_enabled);
+
+    Ok(Html(tera.render("admin_passkeys.html", &ctx).unwrap()))
+}
+
+pub async fn post_passkey_register_start(
+    AdminUser(username): AdminUser,
+    Extension(db): Extension<Database>,
+    Extension(webauthn): Extension<Webauthn>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let credentials = db
+        .list_credentials_for_user(&username)
+        .await
+        .map_err(|e| e.into())?;
+
+    Ok(Json(webauthn.start_registration(&username, &credentials)))
+}
+
+pub async fn post_passkey_register_finish(
+    AdminUser(username): AdminUser,
+    Extension(db): Extension<Database>,
+    Extension(webauthn): Extension<Webauthn>,
+    Json(payload): Json<RegisterPasskey>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let credential = webauthn
+        .finish_registration(&username, &payload)
+        .map_err(|e| e.into())?;
+
+    let name = if payload.name.trim().is_empty() {
+        "Passkey"
+    } else {
+        &payload.name
+    };
+
+    db.create_credential(
+        &username,
+        &credential.id,
+        name,
+        &credential.public_key,
+        credential.sign_count as i64,
+    )
+    .await
+    .map_err(|e| e.into())?;
+
+    tracing::info!("User {} registered a new passkey", username);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn delete_passkey(
+    AdminUser(username): AdminUser,
+    Form(payload): Form<DeletePasskey>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let user = db
+        .get_user(&username)
+        .await
+        .map_err(|e| e.into())?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".to_owned()))?;
+    let credentials = db
+        .list_credentials_for_user(&username)
+        .await
+        .map_err(|e| e.into())?;
+
+    if !user.password_login_enabled && credentials.len() <= 1 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Enable password login before removing your last passkey".into(),
+        ));
+    }
+
+    db.delete_credential(&username, &payload.id)
+        .await
+        .map(|_| Redirect::to("/admin/passkeys"))
+        .map_err(|e| e.into())
+}
+
+pub async fn post_password_login(
+    AdminUser(username): AdminUser,
+    Form(payload): Form<SetPasswordLogin>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let credentials = db
+        .list_credentials_for_user(&username)
+        .await
+        .map_err(|e| e.into())?;
+
+    if !payload.enabled && credentials.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Register a passkey before disabling password login".into(),
+        ));
+    }
+
+    db.set_password_login_enabled(&username, payload.enabled)
+        .await
+        .map(|_| Redirect::to("/admin/passkeys"))
+        .map_err(|e| e.into())
+}
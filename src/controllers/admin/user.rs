@@ -0,0 +1,85 @@
+use axum::{
+    extract::Form,
+    http::StatusCode,
+    response::{Html, IntoResponse},
+    Extension,
+};
+use tera::{Context, Tera};
+
+use crate::{
+    model::forms::user::ChangePassword,
+    services::{
+        auth::{check_current_password, AdminUser},
+        database::Database,
+        password::hash_password,
+    },
+};
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+pub async fn get_admin_password_page(
+    AdminUser(username): AdminUser,
+    Extension(tera): Extension<Tera>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let mut ctx = Context::new();
+
+    ctx.insert("current_page", "password");
+    ctx.insert("username", &username);
+    ctx.insert("min_password_length", &MIN_PASSWORD_LENGTH);
+    ctx.insert("changed", &false);
+
+    Ok(Html(tera.render("admin_password.html", &ctx).unwrap()))
+}
+
+pub async fn post_password(
+    AdminUser(username): AdminUser,
+    Form(payload): Form<ChangePassword>,
+    Extension(tera): Extension<Tera>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    // Logins are remembered by the browser so make sure it's actually the
+    // user at the keyboard asking for the change.
+    if !check_current_password(&username, &payload.current_password, &db).await {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Current password is incorrect".into(),
+        ));
+    }
+
+    if payload.new_password != payload.confirm_password {
+        return Err((StatusCode::BAD_REQUEST, "New passwords do not match".into()));
+    }
+
+    if payload.new_password.trim().chars().count() < MIN_PASSWORD_LENGTH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "New password must be at least {} characters",
+                MIN_PASSWORD_LENGTH
+            ),
+        ));
+    }
+
+    let hash = hash_password(&payload.new_password).map_err(|e| {
+        tracing::error!("Error while hashing password: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to hash password".to_string(),
+        )
+    })?;
+
+    db.change_password(&username, &hash)
+        .await
+        .map_err(|e| e.into())?;
+
+    tracing::info!("Changed password for user {}", username);
+
+    let mut ctx = Context::new();
+
+    ctx.insert("current_page", "password");
+    ctx.insert("username", &username);
+    ctx.insert("min_password_length", &MIN_PASSWORD_LENGTH);
+    ctx.insert("changed", &true);
+
+    Ok(Html(tera.render("admin_password.html", &ctx).unwrap()))
+}
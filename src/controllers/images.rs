@@ -1,30 +1,122 @@
 use axum::{
-    extract::Path,
-    http::{header::CONTENT_TYPE, StatusCode},
+    extract::{Path, Query},
+    http::{HeaderMap, StatusCode},
     response::IntoResponse,
     Extension,
 };
 
-use crate::services::static_files::StaticFiles;
+use crate::{
+    model::forms::image::ThumbnailVersion,
+    services::{
+        auth::AdminUser,
+        database::Database,
+        display::DisplayVariants,
+        static_files::StaticFiles,
+        thumbs::{ThumbnailProfiles, DEFAULT_PROFILE},
+    },
+};
 
+// Watermarking would be pointless if the originals could still be downloaded,
+// so while there's a watermark they're only served by `serve_admin_image`.
 pub async fn serve_image(
     Path(filename): Path<String>,
+    headers: HeaderMap,
     Extension(static_files): Extension<StaticFiles>,
+    Extension(display_variants): Extension<DisplayVariants>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
+    if display_variants.is_watermarked() {
+        return Err((StatusCode::NOT_FOUND, "not found".to_owned()));
+    }
+
     let file = static_files
         .get_image(&filename)
         .await
         .map_err(|e| e.into())?;
-    Ok(([(CONTENT_TYPE, "image/jpeg")], file))
+    file.into_response(&headers).await.map_err(|e| e.into())
+}
+
+/// Originals for the admin pages, which are shown even when the public site
+/// only gets watermarked copies. Kept with the admin routes so checking the
+/// login never happens on the public ones.
+pub async fn serve_admin_image(
+    _: AdminUser,
+    Path(filename): Path<String>,
+    headers: HeaderMap,
+    Extension(static_files): Extension<StaticFiles>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let file = static_files
+        .get_image(&filename)
+        .await
+        .map_err(|e| e.into())?;
+    file.into_response(&headers).await.map_err(|e| e.into())
 }
 
 pub async fn serve_thumb(
     Path(filename): Path<String>,
+    Query(version): Query<ThumbnailVersion>,
+    headers: HeaderMap,
+    Extension(static_files): Extension<StaticFiles>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let versioned = is_current_version(&db, DEFAULT_PROFILE, &filename, version).await?;
+    let file = static_files
+        .get_thumb(DEFAULT_PROFILE, &filename, versioned, &headers)
+        .await
+        .map_err(|e| e.into())?;
+    file.into_response(&headers).await.map_err(|e| e.into())
+}
+
+pub async fn serve_profile_thumb(
+    Path((profile, filename)): Path<(String, String)>,
+    Query(version): Query<ThumbnailVersion>,
+    headers: HeaderMap,
+    Extension(static_files): Extension<StaticFiles>,
+    Extension(thumbnail_profiles): Extension<ThumbnailProfiles>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    if thumbnail_profiles.get(&profile).is_none() {
+        return Err((StatusCode::NOT_FOUND, "not found".to_owned()));
+    }
+
+    let versioned = is_current_version(&db, &profile, &filename, version).await?;
+    let file = static_files
+        .get_thumb(&profile, &filename, versioned, &headers)
+        .await
+        .map_err(|e| e.into())?;
+    file.into_response(&headers).await.map_err(|e| e.into())
+}
+
+// Only a URL with the thumbnail's current version can be cached for good. An
+// old one is still served, but revalidated, since the file under it has
+// changed.
+async fn is_current_version(
+    db: &Database,
+    profile: &str,
+    filename: &str,
+    version: ThumbnailVersion,
+) -> Result<bool, (StatusCode, String)> {
+    let Some(v) = version.v else {
+        return Ok(false);
+    };
+
+    let current = db
+        .get_thumbnail_version(filename, profile)
+        .await
+        .map_err(|e| e.into())?;
+
+    Ok(current.as_deref() == Some(v.as_str()))
+}
+
+// Display copies aren't versioned, they're remade in place when the watermark
+// or widths change and are always revalidated, which their ETag keeps cheap.
+pub async fn serve_display(
+    Path(filename): Path<String>,
+    headers: HeaderMap,
     Extension(static_files): Extension<StaticFiles>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     let file = static_files
-        .get_thumb(&filename)
+        .get_display(&filename, &headers)
         .await
         .map_err(|e| e.into())?;
-    Ok(([(CONTENT_TYPE, "image/jpeg")], file))
+    file.into_response(&headers).await.map_err(|e| e.into())
 }
@@ -1,12 +1,12 @@
 use axum::{
     extract::Path,
-    http::{header::CONTENT_TYPE, StatusCode},
+    http::{HeaderMap, StatusCode},
     response::{Html, IntoResponse},
     Extension,
 };
 use tera::{Context, Tera};
 
-use crate::services::{database::Database, static_files::StaticFiles};
+use crate::services::{database::Database, display::DisplayVariants, static_files::StaticFiles};
 
 pub async fn get_home_page(
     Extension(tera): Extension<Tera>,
@@ -19,7 +19,7 @@ pub async fn get_home_page(
         .await
         .map_err(|e| e.into())?
         .into_iter()
-        .filter(|i| !i.hide_on_homepage)
+        .filter(|i| !i.hide_on_homepage && i.is_processed())
         .collect::<Vec<_>>();
     let categories = db.list_categories().await.map_err(|e| e.into())?;
 
@@ -40,7 +40,10 @@ pub async fn get_category_page(
     let images = db
         .list_images_for_category(&category)
         .await
-        .map_err(|e| e.into())?;
+        .map_err(|e| e.into())?
+        .into_iter()
+        .filter(|i| i.is_processed())
+        .collect::<Vec<_>>();
     let categories = db.list_categories().await.map_err(|e| e.into())?;
 
     ctx.insert("current_page", &category);
@@ -53,15 +56,22 @@ pub async fn get_image_page(
     Path(image): Path<i64>,
     Extension(tera): Extension<Tera>,
     Extension(db): Extension<Database>,
+    Extension(display_variants): Extension<DisplayVariants>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     let mut ctx = Context::new();
 
-    let image = db.get_image_by_id(image).await.map_err(|e| e.into())?;
+    let image = db
+        .get_image_by_id(image)
+        .await
+        .map_err(|e| e.into())?
+        .filter(|i| i.is_processed())
+        .ok_or((StatusCode::NOT_FOUND, "not found".to_owned()))?;
     let categories = db.list_categories().await.map_err(|e| e.into())?;
 
     ctx.insert("current_page", "image");
     ctx.insert("categories", &categories);
     ctx.insert("image", &image);
+    ctx.insert("watermarked", &display_variants.is_watermarked());
 
     Ok(Html(tera.render("images.html", &ctx).unwrap()))
 }
@@ -93,13 +103,9 @@ pub async fn get_faq_page(
     let categories = db.list_categories().await.map_err(|e| e.into())?;
 
     let mut faqs = db.list_faqs().await.map_err(|e| e.into())?;
-    let faqs = faqs
-        .iter_mut()
-        .map(|faq| {
-            faq.answer = markdown::to_html(&faq.answer);
-            faq
-        })
-        .collect::<Vec<_>>();
+    for faq in faqs.iter_mut() {
+        faq.answer = markdown::to_html(&faq.answer);
+    }
 
     ctx.insert("current_page", "faq");
     ctx.insert("categories", &categories);
@@ -110,21 +116,26 @@ pub async fn get_faq_page(
 
 pub async fn serve_styles(
     Path(filename): Path<String>,
+    headers: HeaderMap,
     Extension(static_files): Extension<StaticFiles>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     let file = static_files
-        .get_style(&filename)
+        .get_style(&filename, &headers)
         .await
         .map_err(|e| e.into())?;
 
-    Ok(([(CONTENT_TYPE, "text/css")], file))
+    file.into_response(&headers).await.map_err(|e| e.into())
 }
 
 pub async fn serve_js(
     Path(filename): Path<String>,
+    headers: HeaderMap,
     Extension(static_files): Extension<StaticFiles>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let file = static_files.get_js(&filename).await.map_err(|e| e.into())?;
+    let file = static_files
+        .get_js(&filename, &headers)
+        .await
+        .map_err(|e| e.into())?;
 
-    Ok(([(CONTENT_TYPE, "text/javascript")], file))
+    file.into_response(&headers).await.map_err(|e| e.into())
 }
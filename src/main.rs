@@ -6,12 +6,14 @@ mod services;
 use std::{env, net::SocketAddr};
 
 use axum::{
+    middleware,
     routing::{get, post},
     Extension, Router,
 };
 use clap::Parser;
 use model::error::Error;
 use tera::Tera;
+use tower_http::compression::CompressionLayer;
 use tracing::info;
 
 use controllers::*;
@@ -24,12 +26,28 @@ use crate::{
         category::{delete_category, get_admin_category_page, move_category, post_category},
         faq::{delete_faq, get_admin_faq_page, move_faq, post_faq},
         image::{
-            delete_image, get_admin_edit_image_page, get_admin_images_page, hide_image, move_image,
-            post_image, put_image,
+            delete_image, exempt_image_from_watermark, get_admin_edit_image_page,
+            get_admin_images_page, hide_image, move_image, post_bulk_images, post_image,
+            post_preview_thumbnail_crop, post_regenerate_display_variants, post_replace_image,
+            post_retry_job, put_image,
         },
+        passkey::{
+            delete_passkey, get_admin_login_page, get_admin_passkeys_page, post_logout,
+            post_passkey_login_finish, post_passkey_login_start, post_passkey_register_finish,
+            post_passkey_register_start, post_password_login,
+        },
+        user::{get_admin_password_page, post_password},
+    },
+    image::{
+        get_admin_edit_profile_thumbnail_page, get_admin_edit_thumbnail_page,
+        post_update_thumbnail_crop,
+    },
+    services::{
+        cleanup::remove_orphaned_files, compression, database::Database, display::DisplayVariants,
+        http_cache, ip_allowlist::IpAllowlist, jobs::JobQueue, security_headers::SecurityHeaders,
+        static_files::StaticFiles, thumbs::ThumbnailProfiles, uploads::UploadLimits,
+        watermark::Watermark, webauthn::Webauthn,
     },
-    image::{get_admin_edit_thumbnail_page, post_update_thumbnail_crop},
-    services::{database::Database, static_files::StaticFiles},
 };
 
 #[tokio::main]
@@ -50,22 +68,165 @@ async fn main() -> Result<(), Error> {
 
     let static_files = StaticFiles::new(cli.root_dir);
 
+    static_files.clear_staging().await?;
+    static_files.clear_precompressed().await?;
+
+    // Files of images deleted while the server was down or that couldn't be
+    // removed at the time
+    remove_orphaned_files(&db, &static_files).await?;
+
+    let webauthn = Webauthn::new(cli.webauthn_rp_id, cli.webauthn_origin);
+
+    let watermark = match (
+        &cli.watermark_text,
+        &cli.watermark_font,
+        &cli.watermark_logo,
+    ) {
+        (Some(text), Some(font), _) => Some(Watermark::text(
+            text,
+            font,
+            cli.watermark_position,
+            cli.watermark_opacity,
+            cli.watermark_scale,
+        )?),
+        (_, _, Some(logo)) => Some(Watermark::logo(
+            logo,
+            cli.watermark_position,
+            cli.watermark_opacity,
+            cli.watermark_scale,
+        )?),
+        _ => None,
+    };
+
+    let display_variants = DisplayVariants::new(cli.display_widths, watermark);
+
+    let thumbnail_profiles = ThumbnailProfiles::new(cli.thumbnail_profiles);
+
+    let jobs = JobQueue::start(
+        cli.image_workers,
+        db.clone(),
+        static_files.clone(),
+        display_variants.clone(),
+        thumbnail_profiles.clone(),
+    )
+    .await?;
+
+    info!("Using metadata policy: {:?}", cli.metadata_policy);
+
+    let upload_limits = UploadLimits::new(
+        cli.max_upload_mb,
+        cli.max_request_mb,
+        cli.max_image_dimension,
+    );
+    info!("Using upload limits: {:?}", upload_limits);
+
     info!(
         "Found templates: {}",
         tera.get_template_names().collect::<Vec<&str>>().join(", ")
     );
 
-    let app = Router::new()
-        // Normal
+    let public_headers = SecurityHeaders::public(&cli.public_csp, cli.hsts_max_age)?;
+    let admin_headers = SecurityHeaders::admin(&cli.admin_csp, cli.hsts_max_age)?;
+
+    let public = public_routes()
+        .layer(middleware::from_fn(http_cache::cache_html))
+        .layer(CompressionLayer::new().compress_when(compression::is_compressible_page))
+        .merge(asset_routes())
+        .layer(middleware::from_fn(move |req, next| {
+            public_headers.clone().apply(req, next)
+        }));
+
+    let mut admin = admin_routes()
+        .layer(middleware::from_fn(http_cache::no_store))
+        .layer(CompressionLayer::new().compress_when(compression::is_compressible_page));
+    // On a listener of its own the admin pages need their stylesheets, scripts
+    // and images too, sent with the same headers
+    if cli.admin_bind.is_some() {
+        admin = admin.merge(asset_routes());
+    }
+    if !cli.admin_allow.is_empty() {
+        let allowlist = IpAllowlist::new(cli.admin_allow);
+
+        // Added as a layer so the check happens before any extractor, including
+        // the ones parsing credentials, gets to see the request.
+        admin = admin.layer(middleware::from_fn(move |req, next| {
+            allowlist.clone().check(req, next)
+        }));
+    }
+    let admin = admin.layer(middleware::from_fn(move |req, next| {
+        admin_headers.clone().apply(req, next)
+    }));
+
+    let with_extensions = |router: Router| {
+        router
+            .layer(Extension(tera.clone()))
+            .layer(Extension(static_files.clone()))
+            .layer(Extension(webauthn.clone()))
+            .layer(Extension(display_variants.clone()))
+            .layer(Extension(thumbnail_profiles.clone()))
+            .layer(Extension(cli.metadata_policy))
+            .layer(Extension(upload_limits))
+            .layer(Extension(jobs.clone()))
+            .layer(Extension(db.clone()))
+    };
+
+    match cli.admin_bind {
+        Some(admin_bind) => {
+            let public = with_extensions(public);
+            let admin = with_extensions(admin);
+
+            tokio::join!(serve(cli.bind, public), serve(admin_bind, admin));
+        }
+        None => {
+            let app = with_extensions(public.merge(admin));
+
+            serve(cli.bind, app).await;
+        }
+    }
+
+    Ok(())
+}
+
+async fn serve(addr: SocketAddr, app: Router) {
+    info!("Starting server on `{}` ...", addr);
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
+}
+
+fn public_routes() -> Router {
+    Router::new()
         .route("/", get(get_home_page))
         .route("/faq", get(get_faq_page))
         .route("/about", get(get_about_page))
         .route("/categories/:category", get(get_category_page))
         .route("/art/:image", get(get_image_page))
+}
+
+// Needed by both the public site and the admin pages
+fn asset_routes() -> Router {
+    Router::new()
         .route("/assets/:filename", get(serve_image))
-        .route("/thumbs/:filename", get(serve_thumb))
+        // The router needs the segments they share named the same, it's the
+        // default profile's file or the name of another profile
+        .route("/thumbs/:name", get(serve_thumb))
+        .route("/thumbs/:name/:filename", get(serve_profile_thumb))
+        .route("/display/:filename", get(serve_display))
         .route("/styles/:filename", get(serve_styles))
         .route("/js/:filename", get(serve_js))
+}
+
+fn admin_routes() -> Router {
+    Router::new()
+        // Admin login
+        .route("/admin/login", get(get_admin_login_page))
+        .route("/admin/login/passkey/start", post(post_passkey_login_start))
+        .route(
+            "/admin/login/passkey/finish",
+            post(post_passkey_login_finish),
+        )
+        .route("/admin/logout", post(post_logout))
         // Admin stuff
         .route("/admin", get(get_admin_page))
         .route(
@@ -74,37 +235,56 @@ async fn main() -> Result<(), Error> {
         )
         .route("/admin/categories/move", post(move_category))
         .route("/admin/categories/delete", post(delete_category))
+        .route("/admin/assets/:filename", get(serve_admin_image))
         .route("/admin/images", get(get_admin_images_page).post(post_image))
         .route("/admin/images/edit/:image", get(get_admin_edit_image_page))
         .route(
             "/admin/images/edit-thumbnail/:image",
             get(get_admin_edit_thumbnail_page),
         )
+        .route(
+            "/admin/images/edit-thumbnail/:image/:profile",
+            get(get_admin_edit_profile_thumbnail_page),
+        )
         .route("/admin/images/delete", post(delete_image))
         .route("/admin/images/update", post(put_image))
+        .route("/admin/images/replace", post(post_replace_image))
+        .route("/admin/images/bulk", post(post_bulk_images))
         .route("/admin/images/move", post(move_image))
         .route("/admin/images/hide", post(hide_image))
+        .route("/admin/images/watermark", post(exempt_image_from_watermark))
+        .route(
+            "/admin/images/regenerate",
+            post(post_regenerate_display_variants),
+        )
         .route(
             "/admin/images/update-thumbnail",
             post(post_update_thumbnail_crop),
         )
+        .route(
+            "/admin/images/preview-thumbnail",
+            post(post_preview_thumbnail_crop),
+        )
+        .route("/admin/jobs/retry", post(post_retry_job))
         .route("/admin/about", get(get_admin_about_page).post(post_about))
         .route("/admin/faq", get(get_admin_faq_page).post(post_faq))
         .route("/admin/faq/delete", post(delete_faq))
         .route("/admin/faq/move", post(move_faq))
-        .layer(Extension(tera))
-        .layer(Extension(static_files))
-        .layer(Extension(db));
-
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-
-    info!("Starting server on `127.0.0.1:3000` ...");
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
-
-    Ok(())
+        .route(
+            "/admin/password",
+            get(get_admin_password_page).post(post_password),
+        )
+        .route("/admin/passkeys", get(get_admin_passkeys_page))
+        .route(
+            "/admin/passkeys/register/start",
+            post(post_passkey_register_start),
+        )
+        .route(
+            "/admin/passkeys/register/finish",
+            post(post_passkey_register_finish),
+        )
+        .route("/admin/passkeys/delete", post(delete_passkey))
+        .route("/admin/passkeys/password-login", post(post_password_login))
 }
 
 fn setup_tracing() {
@@ -1,3 +1,3 @@
 pub struct About {
     pub about_text: String,
-}
\ No newline at end of file
+}
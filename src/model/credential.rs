@@ -0,0 +1,13 @@
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct Credential {
+    pub id: String,
+    pub username: String,
+    pub name: String,
+    #[serde(skip)]
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+}
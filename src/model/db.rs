@@ -7,3 +7,21 @@ pub struct CategoryIdAndPosition {
     pub id: String,
     pub position: i64,
 }
+
+/// A file queued for removal, `kind` is the directory it's in: image, thumb
+/// or display
+pub struct OrphanedFile {
+    pub id: i64,
+    pub kind: String,
+    pub filename: String,
+}
+
+/// A job as stored, with its task still as JSON
+pub struct JobRow {
+    pub id: i64,
+    pub image_id: i64,
+    pub task: String,
+    pub status: String,
+    pub attempts: i64,
+    pub last_error: Option<String>,
+}
@@ -6,6 +6,7 @@ use sqlx::migrate::MigrateError;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     #[error(transparent)]
     IO(#[from] io::Error),
@@ -19,10 +20,21 @@ pub enum Error {
     MultipartError(#[from] MultipartError),
     #[error("Invalid path")]
     InvalidPath,
+    #[error("Not found")]
+    NotFound,
     #[error("Image error")]
     Image(#[from] ImageError),
+    #[error("Unsupported image format")]
+    UnsupportedImage,
+    #[error("Malformed image")]
+    MalformedImage,
+    #[error("Upload too large: {0}")]
+    UploadTooLarge(String),
+    #[error("Passkey error: {0}")]
+    Webauthn(&'static str),
 }
 
+#[allow(clippy::from_over_into)]
 impl Into<(StatusCode, String)> for Error {
     fn into(self) -> (StatusCode, String) {
         match self {
@@ -34,7 +46,18 @@ impl Into<(StatusCode, String)> for Error {
                 (StatusCode::INTERNAL_SERVER_ERROR, "Multipart error".into())
             }
             Self::InvalidPath => (StatusCode::BAD_REQUEST, "invalid path".into()),
+            Self::NotFound => (StatusCode::NOT_FOUND, "not found".into()),
             Self::Image(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+            Self::UnsupportedImage => (
+                StatusCode::BAD_REQUEST,
+                "Unsupported file, please upload a JPEG, PNG, WebP, GIF, TIFF or BMP image".into(),
+            ),
+            Self::MalformedImage => (
+                StatusCode::BAD_REQUEST,
+                "The image file is damaged, please export it again and upload the new file".into(),
+            ),
+            Self::UploadTooLarge(err) => (StatusCode::PAYLOAD_TOO_LARGE, err),
+            Self::Webauthn(err) => (StatusCode::BAD_REQUEST, err.into()),
         }
     }
 }
@@ -1,9 +1,17 @@
-use axum::{body::Bytes, extract::Multipart};
-use serde::Deserialize;
+use std::path::Path;
 
-use crate::model::error::Error;
+use axum::extract::Multipart;
+use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize)]
+use crate::{
+    model::error::Error,
+    services::{
+        static_files::{Staging, StaticFiles},
+        uploads::UploadBudget,
+    },
+};
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct Rectangle {
     pub x: f64,
     pub y: f64,
@@ -11,23 +19,25 @@ pub struct Rectangle {
     pub height: f64,
 }
 
-// Parsed from multipart form data
+// Parsed from multipart form data, with the image streamed to a file
 pub struct CreateImage {
     pub name: String,
     pub description: String,
     pub categories: Vec<String>,
-    pub img: Bytes,
     pub img_name: String,
     pub thumbnail_crop_rect: Option<Rectangle>,
 }
 
 impl CreateImage {
-    pub async fn from_multipart(mut payload: Multipart) -> Result<CreateImage, Error> {
+    pub async fn from_multipart(
+        mut payload: Multipart,
+        budget: &mut UploadBudget,
+        img_path: &Path,
+    ) -> Result<CreateImage, Error> {
         let mut name: Option<String> = None;
         let mut description: Option<String> = None;
         let mut categories: Vec<String> = vec![];
         let mut img_name: Option<String> = None;
-        let mut img: Option<Bytes> = None;
         let mut thumbnail_crop_rect: Option<Rectangle> = None;
 
         while let Some(field) = payload.next_field().await? {
@@ -39,13 +49,13 @@ impl CreateImage {
 
             match field_name {
                 "name" => {
-                    name = Some(field.text().await?);
+                    name = Some(budget.read_text(field).await?);
                 }
                 "description" => {
-                    description = Some(field.text().await?);
+                    description = Some(budget.read_text(field).await?);
                 }
                 "category" => {
-                    let category_id = field.text().await?;
+                    let category_id = budget.read_text(field).await?;
                     categories.push(category_id);
                 }
                 "img" => {
@@ -55,11 +65,11 @@ impl CreateImage {
                             .ok_or(Error::IllegalStateError("Missing filename on image upload"))?
                             .into(),
                     );
-                    img = Some(field.bytes().await?);
+                    budget.save_file(field, img_path).await?;
                 }
                 // Optional field
                 "thumbnail_crop_rect" => {
-                    let crop_rect_json = field.text().await?;
+                    let crop_rect_json = budget.read_text(field).await?;
                     thumbnail_crop_rect =
                         serde_json::from_str(&crop_rect_json).map_err(|serde_err| {
                             tracing::error!("Serde error {}", serde_err);
@@ -70,12 +80,11 @@ impl CreateImage {
             }
         }
 
-        match (name, description, img, img_name) {
-            (Some(name), Some(description), Some(img), Some(img_name)) => Ok(CreateImage {
+        match (name, description, img_name) {
+            (Some(name), Some(description), Some(img_name)) => Ok(CreateImage {
                 name,
                 description,
                 categories,
-                img,
                 img_name,
                 thumbnail_crop_rect,
             }),
@@ -86,6 +95,109 @@ impl CreateImage {
     }
 }
 
+// One file of a bulk upload, streamed to its own staging area
+pub struct BulkImage {
+    pub img_name: String,
+    pub staging: Staging,
+    // Why the file couldn't be read, the rest of the batch carries on without it
+    pub error: Option<Error>,
+}
+
+// Parsed from multipart form data. Names are matched to files by position and
+// the description and categories are shared by all of them.
+pub struct BulkCreateImages {
+    pub names: Vec<String>,
+    pub description: String,
+    pub categories: Vec<String>,
+    pub imgs: Vec<BulkImage>,
+}
+
+impl BulkCreateImages {
+    pub async fn from_multipart(
+        payload: Multipart,
+        budget: &mut UploadBudget,
+        static_files: &StaticFiles,
+    ) -> Result<BulkCreateImages, Error> {
+        let mut imgs = vec![];
+
+        match Self::read_fields(payload, budget, static_files, &mut imgs).await {
+            Ok((names, description, categories)) => Ok(BulkCreateImages {
+                names,
+                description,
+                categories,
+                imgs,
+            }),
+            Err(e) => {
+                for img in imgs {
+                    img.staging.discard().await;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn read_fields(
+        mut payload: Multipart,
+        budget: &mut UploadBudget,
+        static_files: &StaticFiles,
+        imgs: &mut Vec<BulkImage>,
+    ) -> Result<(Vec<String>, String, Vec<String>), Error> {
+        let mut names: Vec<String> = vec![];
+        let mut description = String::new();
+        let mut categories: Vec<String> = vec![];
+
+        while let Some(field) = payload.next_field().await? {
+            let field_name = field
+                .name()
+                .ok_or(Error::IllegalStateError("Missing field name"))?;
+
+            match field_name {
+                "name" => {
+                    names.push(budget.read_text(field).await?);
+                }
+                "description" => {
+                    description = budget.read_text(field).await?;
+                }
+                "category" => {
+                    let category_id = budget.read_text(field).await?;
+                    categories.push(category_id);
+                }
+                "img" => {
+                    let img_name: String = field
+                        .file_name()
+                        .ok_or(Error::IllegalStateError("Missing filename on image upload"))?
+                        .into();
+
+                    let staging = static_files.stage().await?;
+                    let error = match budget.save_file(field, &staging.upload_path()).await {
+                        Ok(()) => None,
+                        // Too large files are skipped, but once the request
+                        // is too large nothing more of it can be read
+                        Err(e @ Error::UploadTooLarge(_)) if !budget.is_spent() => Some(e),
+                        Err(e) => {
+                            staging.discard().await;
+                            return Err(e);
+                        }
+                    };
+
+                    imgs.push(BulkImage {
+                        img_name,
+                        staging,
+                        error,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        if imgs.is_empty() {
+            return Err(Error::IllegalStateError("No images were uploaded"));
+        }
+
+        Ok((names, description, categories))
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct UpdateImage {
     pub id: i64,
@@ -94,7 +206,10 @@ pub struct UpdateImage {
     pub categories: Vec<String>,
 }
 impl UpdateImage {
-    pub async fn from_multipart(mut payload: Multipart) -> Result<UpdateImage, Error> {
+    pub async fn from_multipart(
+        mut payload: Multipart,
+        budget: &mut UploadBudget,
+    ) -> Result<UpdateImage, Error> {
         let mut id: Option<i64> = None;
         let mut name: Option<String> = None;
         let mut description: Option<String> = None;
@@ -107,16 +222,16 @@ impl UpdateImage {
 
             match field_name {
                 "id" => {
-                    id = Some(field.text().await?.parse().unwrap());
+                    id = Some(budget.read_text(field).await?.parse().unwrap());
                 }
                 "name" => {
-                    name = Some(field.text().await?);
+                    name = Some(budget.read_text(field).await?);
                 }
                 "description" => {
-                    description = Some(field.text().await?);
+                    description = Some(budget.read_text(field).await?);
                 }
                 "category" => {
-                    let category_id = field.text().await?;
+                    let category_id = budget.read_text(field).await?;
                     categories.push(category_id);
                 }
                 _ => {}
@@ -137,6 +252,57 @@ impl UpdateImage {
     }
 }
 
+// A new file for an existing image, parsed from multipart form data with the
+// image streamed to a file
+pub struct ReplaceImage {
+    pub id: i64,
+    pub img_name: String,
+}
+
+impl ReplaceImage {
+    pub async fn from_multipart(
+        mut payload: Multipart,
+        budget: &mut UploadBudget,
+        img_path: &Path,
+    ) -> Result<ReplaceImage, Error> {
+        let mut id: Option<i64> = None;
+        let mut img_name: Option<String> = None;
+
+        while let Some(field) = payload.next_field().await? {
+            let field_name = field
+                .name()
+                .ok_or(Error::IllegalStateError("Missing field name"))?;
+
+            match field_name {
+                "id" => {
+                    id = Some(
+                        budget
+                            .read_text(field)
+                            .await?
+                            .parse()
+                            .map_err(|_| Error::IllegalStateError("Malformed image id"))?,
+                    );
+                }
+                "img" => {
+                    img_name = Some(
+                        field
+                            .file_name()
+                            .ok_or(Error::IllegalStateError("Missing filename on image upload"))?
+                            .into(),
+                    );
+                    budget.save_file(field, img_path).await?;
+                }
+                _ => {}
+            }
+        }
+
+        match (id, img_name) {
+            (Some(id), Some(img_name)) => Ok(ReplaceImage { id, img_name }),
+            _ => Err(Error::IllegalStateError("Missing fields, either id or img")),
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct DeleteImage {
     pub id: i64,
@@ -154,8 +320,33 @@ pub struct HideImage {
     pub hide: bool,
 }
 
+#[derive(Deserialize)]
+pub struct ExemptImageFromWatermark {
+    pub id: i64,
+    pub exempt: bool,
+}
+
+#[derive(Deserialize)]
+pub struct RegenerateDisplayVariants {
+    // All images when missing
+    pub id: Option<i64>,
+}
+
 #[derive(Deserialize)]
 pub struct UpdateThumbnailCrop {
     pub id: i64,
+    // The default profile when missing
+    pub profile: Option<String>,
     pub thumbnail_crop_rect: String,
 }
+
+// The `?v=` of a thumbnail URL
+#[derive(Deserialize)]
+pub struct ThumbnailVersion {
+    pub v: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RetryJob {
+    pub id: i64,
+}
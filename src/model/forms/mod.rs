@@ -2,3 +2,5 @@ pub mod about;
 pub mod category;
 pub mod faq;
 pub mod image;
+pub mod passkey;
+pub mod user;
@@ -0,0 +1,38 @@
+use serde::Deserialize;
+
+// Sent as JSON by js/passkeys.js, binary fields are base64url encoded
+#[derive(Deserialize)]
+pub struct RegisterPasskey {
+    pub name: String,
+    pub id: String,
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+}
+
+#[derive(Deserialize)]
+pub struct StartPasskeyLogin {
+    pub username: Option<String>,
+}
+
+// Sent as JSON by js/passkeys.js, binary fields are base64url encoded
+#[derive(Deserialize)]
+pub struct FinishPasskeyLogin {
+    pub id: String,
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    pub authenticator_data: String,
+    pub signature: String,
+}
+
+#[derive(Deserialize)]
+pub struct DeletePasskey {
+    pub id: String,
+}
+
+#[derive(Deserialize)]
+pub struct SetPasswordLogin {
+    pub enabled: bool,
+}
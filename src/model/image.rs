@@ -1,7 +1,11 @@
-use super::category::Category;
+use super::{category::Category, forms::image::Rectangle};
+
+use std::collections::HashMap;
 
 use serde::Serialize;
 
+use crate::services::thumbs::DEFAULT_PROFILE;
+
 #[derive(Serialize)]
 pub struct Image {
     pub id: i64,
@@ -11,4 +15,90 @@ pub struct Image {
     pub categories: Vec<Category>,
     pub position: i64,
     pub hide_on_homepage: bool,
+    pub watermark_exempt: bool,
+    // Missing for images uploaded before it was recorded
+    pub format: Option<String>,
+    /// Thumbnails and display copies are saved under this name, with the
+    /// extension of the format they're in
+    pub web_filename: String,
+    pub display_variants: Vec<DisplayVariant>,
+    /// Added to thumbnail URLs as `?v=`, by profile name. Missing for
+    /// thumbnails made before versions were recorded.
+    pub thumbnail_versions: HashMap<String, String>,
+    // The rest are missing until the image has been processed since they were
+    // recorded. Sizes are in pixels, turned the way the image is shown.
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    pub thumb_width: Option<i64>,
+    pub thumb_height: Option<i64>,
+    /// As #rrggbb
+    pub dominant_color: Option<String>,
+    /// A tiny copy of the thumbnail as a data URI, to show while it loads
+    pub placeholder: Option<String>,
+}
+
+impl Image {
+    /// Whether the default thumbnail has been made, until then the image is
+    /// left out of public pages since there's nothing to show for it
+    pub fn is_processed(&self) -> bool {
+        self.thumbnail_versions.contains_key(DEFAULT_PROFILE)
+    }
+}
+
+/// The crop saved for one of an image's thumbnail profiles
+#[derive(Serialize)]
+pub struct ThumbnailCrop {
+    pub profile: String,
+    pub rect: Rectangle,
+}
+
+/// How one file of a bulk upload went
+#[derive(Serialize)]
+pub struct BulkUploadResult {
+    pub name: String,
+    pub img_name: String,
+    pub error: Option<String>,
+}
+
+/// A copy of an image scaled down to a width from the display ladder
+#[derive(Serialize)]
+pub struct DisplayVariant {
+    pub width: u32,
+    pub filename: String,
+}
+
+impl DisplayVariant {
+    /// Builds the variants from the JSON array of widths stored with an image,
+    /// named after its `web_filename`
+    pub fn from_widths(filename: &str, widths_json: &str) -> Vec<DisplayVariant> {
+        let mut widths: Vec<u32> = serde_json::from_str(widths_json).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring bad display widths for {}: {}", filename, e);
+            vec![]
+        });
+        widths.sort_unstable();
+
+        widths
+            .into_iter()
+            .map(|width| DisplayVariant {
+                width,
+                filename: display_filename(filename, width),
+            })
+            .collect()
+    }
+}
+
+/// Reads the JSON object of thumbnail versions stored with an image
+pub fn thumbnail_versions(filename: &str, versions_json: &str) -> HashMap<String, String> {
+    serde_json::from_str(versions_json).unwrap_or_else(|e| {
+        tracing::warn!("Ignoring bad thumbnail versions for {}: {}", filename, e);
+        HashMap::new()
+    })
+}
+
+// `abc.jpg` at 800 pixels wide is `abc-800.jpg`
+pub fn display_filename(filename: &str, width: u32) -> String {
+    match filename.rsplit_once('.') {
+        Some((stem, ext)) => format!("{}-{}.{}", stem, width, ext),
+        None => format!("{}-{}", filename, width),
+    }
 }
@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+
+use crate::services::thumbs::DEFAULT_PROFILE;
+
+pub const PENDING: &str = "pending";
+pub const RUNNING: &str = "running";
+pub const FAILED: &str = "failed";
+
+/// Processing of an image's files, stored as JSON with its job
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Task {
+    /// Made with the profile's saved crop, when it has one
+    Thumbnail {
+        profile: String,
+    },
+    ThumbnailDerivatives,
+    DisplayVariants,
+}
+
+impl Task {
+    pub fn describe(&self) -> String {
+        match self {
+            Task::Thumbnail { profile } if profile == DEFAULT_PROFILE => {
+                "Making thumbnail".to_owned()
+            }
+            Task::Thumbnail { profile } => format!("Making {} thumbnail", profile),
+            Task::ThumbnailDerivatives => "Making WebP and AVIF thumbnails".to_owned(),
+            Task::DisplayVariants => "Making display sizes".to_owned(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Job {
+    pub id: i64,
+    pub image_id: i64,
+    pub task: Task,
+    pub description: String,
+    pub status: String,
+    pub attempts: i64,
+    pub last_error: Option<String>,
+}
@@ -1,8 +1,10 @@
 pub mod about;
 pub mod category;
+pub mod credential;
 pub mod db;
 pub mod error;
 pub mod faq;
 pub mod forms;
 pub mod image;
+pub mod job;
 pub mod user;
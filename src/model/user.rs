@@ -1,4 +1,5 @@
 pub struct User {
     pub username: String,
     pub password_hash: String,
+    pub password_login_enabled: bool,
 }
@@ -1,10 +1,21 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use axum::async_trait;
 use axum::extract::{FromRequest, RequestParts};
-use axum::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
-use axum::http::{HeaderMap, StatusCode};
+use axum::http::header::{AUTHORIZATION, COOKIE, WWW_AUTHENTICATE};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::model::user::User;
 
 use super::database::Database;
-use super::password::verify_password;
+use super::password::{hash_password, needs_rehash, verify_password};
+use super::webauthn;
+
+pub const SESSION_COOKIE: &str = "admin_session";
+pub const SESSION_LENGTH: Duration = Duration::from_secs(12 * 60 * 60);
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct AuthBasic(pub (String, String));
@@ -67,10 +78,134 @@ where
     }
 }
 
+/// An authenticated admin, either from a passkey login session cookie or from
+/// basic auth credentials for a user that still allows password logins.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AdminUser(pub String);
+
+#[async_trait]
+impl<B> FromRequest<B> for AdminUser
+where
+    B: Send,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: &mut RequestParts<B>) -> std::result::Result<Self, Self::Rejection> {
+        let db = req
+            .extensions()
+            .get::<Database>()
+            .cloned()
+            .ok_or_else(|| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+        if let Some(token) = session_token(req.headers()) {
+            if let Ok(Some(username)) = db.get_session_user(&hash_session_token(token), now()).await
+            {
+                return Ok(AdminUser(username));
+            }
+        }
+
+        match AuthBasic::from_request(req).await {
+            Ok(AuthBasic((username, password))) => {
+                if check_password_for_user(&username, &password, &db).await {
+                    Ok(AdminUser(username))
+                } else {
+                    Err(login_required())
+                }
+            }
+            Err((StatusCode::UNAUTHORIZED, _, _)) => Err(login_required()),
+            Err(rejection) => Err(rejection.into_response()),
+        }
+    }
+}
+
+// The browser shows the basic auth prompt, if that's cancelled the body is
+// displayed instead which lets users without a password find the passkey login.
+fn login_required() -> Response {
+    let mut headers = HeaderMap::new();
+    headers.append(WWW_AUTHENTICATE, HeaderValue::from_static("Basic"));
+
+    (
+        StatusCode::UNAUTHORIZED,
+        headers,
+        Html(r#"<p>Not logged in. <a href="/admin/login">Log in with a passkey</a></p>"#),
+    )
+        .into_response()
+}
+
+pub fn session_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+}
+
+pub fn generate_session_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    webauthn::encode(&bytes)
+}
+
+// Only a hash of the token is stored so a leaked database can't be used to
+// hijack sessions.
+pub fn hash_session_token(token: &str) -> String {
+    webauthn::encode(&Sha256::digest(token.as_bytes()))
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
 pub async fn check_password_for_user(username: &str, password: &str, db: &Database) -> bool {
+    match db.get_user(username).await {
+        Ok(Some(user)) if user.password_login_enabled => {
+            verify_password_for_user(&user, password, db).await
+        }
+        _ => false,
+    }
+}
+
+// Checks the password regardless of whether it can be used to log in, for
+// confirming the current password before it's changed.
+pub async fn check_current_password(username: &str, password: &str, db: &Database) -> bool {
     if let Ok(Some(user)) = db.get_user(username).await {
-        matches!(verify_password(password, &user.password_hash), Ok(true))
+        verify_password_for_user(&user, password, db).await
     } else {
         false
     }
 }
+
+async fn verify_password_for_user(user: &User, password: &str, db: &Database) -> bool {
+    if !matches!(verify_password(password, &user.password_hash), Ok(true)) {
+        return false;
+    }
+
+    // The password is known to be good at this point so take the chance to
+    // upgrade hashes made with weaker parameters than we use today.
+    if matches!(needs_rehash(&user.password_hash), Ok(true)) {
+        rehash_password(&user.username, password, db).await;
+    }
+
+    true
+}
+
+async fn rehash_password(username: &str, password: &str, db: &Database) {
+    let result = match hash_password(password) {
+        Ok(hash) => db
+            .update_password_hash(username, &hash)
+            .await
+            .map_err(anyhow::Error::from),
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) => tracing::info!("Rehashed password for user {}", username),
+        Err(e) => tracing::error!("Failed to rehash password for user {}: {}", username, e),
+    }
+}
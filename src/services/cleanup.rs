@@ -0,0 +1,53 @@
+use crate::model::{db::OrphanedFile, error::Error, image::Image};
+
+use super::{database::Database, static_files::StaticFiles};
+
+pub const ORIGINAL: &str = "image";
+pub const THUMB: &str = "thumb";
+pub const DISPLAY: &str = "display";
+
+/// Every file kept on disk for an image, as the kind and name to queue them
+/// under once nothing refers to them. Thumbnails and display copies take
+/// their modern format copies with them.
+pub fn image_files(image: &Image) -> Vec<(&'static str, String)> {
+    let mut files = vec![
+        (ORIGINAL, image.filename.clone()),
+        (THUMB, image.web_filename.clone()),
+    ];
+    files.extend(
+        image
+            .display_variants
+            .iter()
+            .map(|v| (DISPLAY, v.filename.clone())),
+    );
+
+    files
+}
+
+/// Removes the queued files from disk. Files that can't be removed stay
+/// queued for the next sweep, which happens at startup and after every delete.
+pub async fn remove_orphaned_files(db: &Database, static_files: &StaticFiles) -> Result<(), Error> {
+    for file in db.list_orphaned_files().await? {
+        match remove(&file, static_files).await {
+            // Already gone is as good as removed
+            Ok(()) | Err(Error::NotFound) => db.forget_orphaned_file(file.id).await?,
+            Err(e) => tracing::warn!(
+                "Failed to remove {} {}, will try again later: {}",
+                file.kind,
+                file.filename,
+                e
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+async fn remove(file: &OrphanedFile, static_files: &StaticFiles) -> Result<(), Error> {
+    match file.kind.as_str() {
+        ORIGINAL => static_files.delete_image(&file.filename).await,
+        THUMB => static_files.delete_thumb(&file.filename).await,
+        DISPLAY => static_files.delete_display(&file.filename).await,
+        _ => Err(Error::IllegalStateError("Unknown kind of orphaned file")),
+    }
+}
@@ -0,0 +1,322 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use axum::http::{
+    header::{ACCEPT_ENCODING, CONTENT_TYPE},
+    Extensions, HeaderMap, StatusCode, Version,
+};
+use flate2::{write::GzEncoder, Compression};
+use uuid::Uuid;
+
+use crate::model::error::Error;
+
+const BROTLI_QUALITY: u32 = 11;
+const BROTLI_WINDOW: u32 = 22;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn header_value(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gz",
+        }
+    }
+}
+
+/// Picks the encoding to use from `Accept-Encoding`, preferring brotli when
+/// the client is equally happy with either.
+pub fn preferred_encoding(request_headers: &HeaderMap) -> Option<Encoding> {
+    let accept = request_headers.get(ACCEPT_ENCODING)?.to_str().ok()?;
+
+    let mut brotli = None;
+    let mut gzip = None;
+    let mut wildcard = None;
+
+    for entry in accept.split(',') {
+        let mut params = entry.split(';');
+        let coding = params
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let quality = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        match coding.as_str() {
+            "br" => brotli = Some(quality),
+            "gzip" | "x-gzip" => gzip = Some(quality),
+            "*" => wildcard = Some(quality),
+            _ => {}
+        }
+    }
+
+    let brotli = brotli.or(wildcard).unwrap_or(0.0);
+    let gzip = gzip.or(wildcard).unwrap_or(0.0);
+
+    if brotli > 0.0 && brotli >= gzip {
+        Some(Encoding::Brotli)
+    } else if gzip > 0.0 {
+        Some(Encoding::Gzip)
+    } else {
+        None
+    }
+}
+
+/// Finds the compressed copy of a stylesheet or script that sits next to it,
+/// making it first if it's missing or out of date.
+pub async fn precompressed(path: &Path, encoding: Encoding) -> Result<PathBuf, Error> {
+    let mut compressed = path.as_os_str().to_owned();
+    compressed.push(".");
+    compressed.push(encoding.extension());
+    let compressed = PathBuf::from(compressed);
+
+    let modified = tokio::fs::metadata(path).await?.modified()?;
+    // Copies are given the original's mtime, so any difference means it was
+    // edited, including when it was put back to an older version
+    let is_current = match tokio::fs::metadata(&compressed).await {
+        Ok(metadata) => metadata.modified()? == modified,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+        Err(e) => return Err(e.into()),
+    };
+
+    if !is_current {
+        tracing::info!("Compressing: {}", path.display());
+
+        let source = path.to_owned();
+        let target = compressed.clone();
+        tokio::task::spawn_blocking(move || compress_file(&source, &target, encoding, modified))
+            .await
+            .map_err(io::Error::other)??;
+    }
+
+    Ok(compressed)
+}
+
+/// Whether a file is a compressed copy made by `precompressed`, or what's left
+/// of one that was being made
+pub fn is_compressed_copy(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("br" | "gz" | "tmp")
+    )
+}
+
+// Written to a temporary file first so that a request arriving part way
+// through never sees half a file, and two requests racing to make the same
+// file don't interleave.
+fn compress_file(
+    source: &Path,
+    target: &Path,
+    encoding: Encoding,
+    modified: SystemTime,
+) -> io::Result<()> {
+    let temp = target.with_extension(format!("{}.tmp", Uuid::new_v4()));
+
+    let result = (|| {
+        let mut input = BufReader::new(File::open(source)?);
+        let output = BufWriter::new(File::create(&temp)?);
+
+        let output = match encoding {
+            Encoding::Brotli => {
+                let mut writer =
+                    brotli::CompressorWriter::new(output, 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+                io::copy(&mut input, &mut writer)?;
+                writer.into_inner()
+            }
+            Encoding::Gzip => {
+                let mut writer = GzEncoder::new(output, Compression::best());
+                io::copy(&mut input, &mut writer)?;
+                writer.finish()?
+            }
+        };
+
+        let file = output.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+
+        // Matching the original's mtime means the copy is only considered
+        // current until the original is next edited.
+        file.set_modified(modified)?;
+
+        std::fs::rename(&temp, target)
+    })();
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+
+    result
+}
+
+/// Predicate for compressing rendered pages on the fly. Everything else we
+/// serve is either an image, which is already compressed, or a stylesheet or
+/// script, which has a precompressed copy.
+pub fn is_compressible_page(
+    status: StatusCode,
+    _: Version,
+    headers: &HeaderMap,
+    _: &Extensions,
+) -> bool {
+    status == StatusCode::OK
+        && headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.starts_with("text/html"))
+            .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        time::Duration,
+    };
+
+    use axum::http::HeaderValue;
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    fn preferred(accept: &str) -> Option<Encoding> {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_str(accept).unwrap());
+
+        preferred_encoding(&headers)
+    }
+
+    #[test]
+    fn prefers_brotli_over_gzip() {
+        assert_eq!(preferred("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(preferred("br;q=0.5, gzip;q=0.5"), Some(Encoding::Brotli));
+        assert_eq!(preferred("br;q=0.5, gzip;q=0.8"), Some(Encoding::Gzip));
+        assert_eq!(preferred("x-gzip"), Some(Encoding::Gzip));
+        assert_eq!(preferred("identity"), None);
+        assert_eq!(preferred_encoding(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn never_picks_an_encoding_with_no_quality() {
+        assert_eq!(preferred("br;q=0, gzip"), Some(Encoding::Gzip));
+        assert_eq!(preferred("br;q=0, gzip;q=0"), None);
+        assert_eq!(preferred("GZIP; q=0.0"), None);
+    }
+
+    #[test]
+    fn applies_the_wildcard_to_encodings_not_listed() {
+        assert_eq!(preferred("*"), Some(Encoding::Brotli));
+        assert_eq!(preferred("br;q=0, *"), Some(Encoding::Gzip));
+        assert_eq!(preferred("gzip, *;q=0"), Some(Encoding::Gzip));
+        assert_eq!(preferred("*;q=0"), None);
+    }
+
+    // A stylesheet of its own under the system temp directory, removed again
+    // when the test is done
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("compression-{}.css", Uuid::new_v4()));
+            std::fs::write(&path, contents).unwrap();
+
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+            for extension in ["css.br", "css.gz"] {
+                let _ = std::fs::remove_file(self.0.with_extension(extension));
+            }
+        }
+    }
+
+    fn gunzip(path: &Path) -> String {
+        let mut contents = String::new();
+        GzDecoder::new(File::open(path).unwrap())
+            .read_to_string(&mut contents)
+            .unwrap();
+
+        contents
+    }
+
+    fn modified(path: &Path) -> SystemTime {
+        std::fs::metadata(path).unwrap().modified().unwrap()
+    }
+
+    fn set_modified(path: &Path, time: SystemTime) {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn makes_a_copy_with_the_originals_mtime() {
+        let file = TempFile::new("body { color: red }");
+
+        let compressed = precompressed(&file.0, Encoding::Gzip).await.unwrap();
+
+        assert_eq!(compressed, file.0.with_extension("css.gz"));
+        assert_eq!(gunzip(&compressed), "body { color: red }");
+        assert_eq!(modified(&compressed), modified(&file.0));
+    }
+
+    #[tokio::test]
+    async fn reuses_a_current_copy() {
+        let file = TempFile::new("body { color: red }");
+        let compressed = precompressed(&file.0, Encoding::Gzip).await.unwrap();
+
+        // Only the mtime is checked, so a copy that was changed some other
+        // way is left alone
+        let mut copy = GzEncoder::new(Vec::new(), Compression::fast());
+        copy.write_all(b"reused").unwrap();
+        std::fs::write(&compressed, copy.finish().unwrap()).unwrap();
+        set_modified(&compressed, modified(&file.0));
+
+        precompressed(&file.0, Encoding::Gzip).await.unwrap();
+        assert_eq!(gunzip(&compressed), "reused");
+    }
+
+    #[tokio::test]
+    async fn remakes_the_copy_when_the_mtime_changes() {
+        let file = TempFile::new("body { color: red }");
+        let compressed = precompressed(&file.0, Encoding::Gzip).await.unwrap();
+
+        // Put back to an older version, so it's older than the copy
+        std::fs::write(&file.0, "body { color: blue }").unwrap();
+        let older = modified(&compressed) - Duration::from_secs(3600);
+        set_modified(&file.0, older);
+
+        precompressed(&file.0, Encoding::Gzip).await.unwrap();
+        assert_eq!(gunzip(&compressed), "body { color: blue }");
+        assert_eq!(modified(&compressed), older);
+    }
+
+    #[test]
+    fn recognises_compressed_copies() {
+        assert!(is_compressed_copy(Path::new("style.css.br")));
+        assert!(is_compressed_copy(Path::new("style.css.gz")));
+        assert!(is_compressed_copy(Path::new("style.css.1234.tmp")));
+        assert!(!is_compressed_copy(Path::new("style.css")));
+    }
+}
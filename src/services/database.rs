@@ -1,18 +1,22 @@
-use std::path::Path;
+use std::{future::Future, path::Path};
 
-use sqlx::SqlitePool;
+use sqlx::{SqliteConnection, SqlitePool};
 
 use crate::model::{
     about::About,
     category::Category,
-    db::{CategoryIdAndPosition, ImageIdAndPosition},
+    credential::Credential,
+    db::{CategoryIdAndPosition, ImageIdAndPosition, JobRow, OrphanedFile},
     error::Error,
     faq::Faq,
-    forms::faq::CreateFaq,
-    image::Image,
+    forms::{faq::CreateFaq, image::Rectangle},
+    image::{thumbnail_versions, DisplayVariant, Image, ThumbnailCrop},
+    job::{Job, Task, FAILED, PENDING, RUNNING},
     user::User,
 };
 
+use super::{placeholder::Placeholder, thumbs::DEFAULT_PROFILE};
+
 #[derive(Clone)]
 pub struct Database {
     pool: SqlitePool,
@@ -82,12 +86,22 @@ impl Database {
         Ok(categories)
     }
 
+    /// Saves a new image, with the crop chosen for its default thumbnail, and
+    /// queues the jobs that make its other files. `publish` puts its files in
+    /// place and is run inside the transaction, so the row is only kept if
+    /// that worked.
+    #[allow(clippy::too_many_arguments)]
     pub async fn create_image(
         &self,
         name: String,
         description: String,
         filename: String,
+        web_filename: &str,
         categories: Vec<String>,
+        format: &str,
+        thumbnail_crop_rect: Option<Rectangle>,
+        tasks: &[Task],
+        publish: impl Future<Output = Result<(), Error>>,
     ) -> Result<(), Error> {
         let mut tx = self.pool.begin().await?;
 
@@ -96,10 +110,15 @@ impl Database {
         let filename = filename.trim();
 
         let image_id = sqlx::query!(
-            "INSERT INTO images (name, description, filename) VALUES (?1, ?2, ?3)",
+            r#"
+            INSERT INTO images (name, description, filename, web_filename, format)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            "#,
             name,
             description,
-            filename
+            filename,
+            web_filename,
+            format
         )
         .execute(&mut tx)
         .await?
@@ -115,6 +134,16 @@ impl Database {
             .await?;
         }
 
+        if let Some(rect) = thumbnail_crop_rect {
+            insert_thumbnail_crop(&mut tx, image_id, DEFAULT_PROFILE, &rect).await?;
+        }
+
+        for task in tasks {
+            insert_job(&mut tx, image_id, task).await?;
+        }
+
+        publish.await?;
+
         tx.commit().await?;
 
         Ok(())
@@ -165,6 +194,257 @@ impl Database {
         Ok(())
     }
 
+    /// Points an image at a new file, keeping its id, position and categories.
+    /// The old image's files are queued for removal, and its thumbnail crops
+    /// and waiting jobs are dropped, since they were for the old file.
+    /// `publish` puts the new files in place, as with `create_image`.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn replace_image_file(
+        &self,
+        image_id: i64,
+        filename: &str,
+        web_filename: &str,
+        format: &str,
+        tasks: &[Task],
+        old_files: &[(&str, String)],
+        publish: impl Future<Output = Result<(), Error>>,
+    ) -> Result<(), Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE images
+            SET filename = ?1, web_filename = ?2, format = ?3, display_widths = '[]',
+              thumbnail_versions = '{}', width = NULL, height = NULL, thumb_width = NULL,
+              thumb_height = NULL, dominant_color = NULL, placeholder = NULL
+            WHERE id = ?4
+            "#,
+            filename,
+            web_filename,
+            format,
+            image_id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query!("DELETE FROM thumbnail_crops WHERE image_id = ?1", image_id)
+            .execute(&mut tx)
+            .await?;
+
+        sqlx::query!(
+            "DELETE FROM jobs WHERE image_id = ?1 AND status != ?2",
+            image_id,
+            RUNNING
+        )
+        .execute(&mut tx)
+        .await?;
+
+        for task in tasks {
+            insert_job(&mut tx, image_id, task).await?;
+        }
+
+        for (kind, filename) in old_files {
+            sqlx::query!(
+                "INSERT INTO orphaned_files (kind, filename) VALUES (?1, ?2)",
+                kind,
+                filename
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        publish.await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    // The setters below are for files made in the background, so they only
+    // write to the image while it still has the file they were made from and
+    // return false once it's been replaced or deleted.
+
+    pub async fn set_display_widths(
+        &self,
+        image_id: i64,
+        filename: &str,
+        display_widths: &[u32],
+    ) -> Result<bool, Error> {
+        let display_widths = serde_json::to_string(display_widths).unwrap();
+
+        let result = sqlx::query!(
+            "UPDATE images SET display_widths = ?1 WHERE id = ?2 AND filename = ?3",
+            display_widths,
+            image_id,
+            filename
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn set_image_size(
+        &self,
+        image_id: i64,
+        filename: &str,
+        width: u32,
+        height: u32,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query!(
+            "UPDATE images SET width = ?1, height = ?2 WHERE id = ?3 AND filename = ?4",
+            width,
+            height,
+            image_id,
+            filename
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn set_thumbnail_placeholder(
+        &self,
+        image_id: i64,
+        filename: &str,
+        placeholder: &Placeholder,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE images
+            SET thumb_width = ?1, thumb_height = ?2, dominant_color = ?3, placeholder = ?4
+            WHERE id = ?5 AND filename = ?6
+            "#,
+            placeholder.width,
+            placeholder.height,
+            placeholder.dominant_color,
+            placeholder.preview,
+            image_id,
+            filename
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn set_thumbnail_version(
+        &self,
+        image_id: i64,
+        filename: &str,
+        profile: &str,
+        version: &str,
+    ) -> Result<bool, Error> {
+        // Quoted, since profile names can have a - in them
+        let path = format!("$.\"{}\"", profile);
+
+        let result = sqlx::query!(
+            r#"
+            UPDATE images SET thumbnail_versions = json_set(thumbnail_versions, ?1, ?2)
+            WHERE id = ?3 AND filename = ?4
+            "#,
+            path,
+            version,
+            image_id,
+            filename
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The version of a profile's thumbnail saved under this name
+    pub async fn get_thumbnail_version(
+        &self,
+        web_filename: &str,
+        profile: &str,
+    ) -> Result<Option<String>, Error> {
+        let path = format!("$.\"{}\"", profile);
+
+        let version = sqlx::query_scalar!(
+            r#"
+            SELECT json_extract(thumbnail_versions, ?1) AS "version?: String"
+            FROM images WHERE COALESCE(web_filename, filename) = ?2
+            "#,
+            path,
+            web_filename
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(version.flatten())
+    }
+
+    pub async fn set_thumbnail_crop(
+        &self,
+        image_id: i64,
+        profile: &str,
+        rect: &Rectangle,
+    ) -> Result<(), Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        insert_thumbnail_crop(&mut conn, image_id, profile, rect).await
+    }
+
+    pub async fn get_thumbnail_crop(
+        &self,
+        image_id: i64,
+        profile: &str,
+    ) -> Result<Option<Rectangle>, Error> {
+        let crop = sqlx::query_as!(
+            Rectangle,
+            r#"
+            SELECT
+              x      AS "x: f64",
+              y      AS "y: f64",
+              width  AS "width: f64",
+              height AS "height: f64"
+            FROM thumbnail_crops
+            WHERE image_id = ?1 AND profile = ?2
+            "#,
+            image_id,
+            profile
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(crop)
+    }
+
+    pub async fn list_thumbnail_crops(&self, image_id: i64) -> Result<Vec<ThumbnailCrop>, Error> {
+        let crops = sqlx::query!(
+            r#"
+            SELECT
+              profile,
+              x      AS "x: f64",
+              y      AS "y: f64",
+              width  AS "width: f64",
+              height AS "height: f64"
+            FROM thumbnail_crops
+            WHERE image_id = ?1
+            ORDER BY profile ASC
+            "#,
+            image_id
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| ThumbnailCrop {
+            profile: row.profile,
+            rect: Rectangle {
+                x: row.x,
+                y: row.y,
+                width: row.width,
+                height: row.height,
+            },
+        })
+        .collect();
+
+        Ok(crops)
+    }
+
     pub async fn list_images(&self) -> Result<Vec<Image>, Error> {
         let rows: Vec<_> = sqlx::query!(
             r#"
@@ -175,6 +455,17 @@ impl Database {
               images.filename         AS image_filename, 
               images.position         AS "image_position!", 
               images.hide_on_homepage AS "image_hide_on_homepage!",
+              images.watermark_exempt AS "image_watermark_exempt!",
+              images.format           AS image_format,
+              COALESCE(images.web_filename, images.filename) AS "image_web_filename!: String",
+              images.display_widths   AS "image_display_widths!",
+              images.thumbnail_versions AS "image_thumbnail_versions!",
+              images.width            AS image_width,
+              images.height           AS image_height,
+              images.thumb_width      AS image_thumb_width,
+              images.thumb_height     AS image_thumb_height,
+              images.dominant_color   AS image_dominant_color,
+              images.placeholder      AS image_placeholder,
               categories.id           AS category_id,
               categories.name         AS category_name,
               categories.position     AS category_position
@@ -213,6 +504,23 @@ impl Database {
                         .collect(),
                     position: first.image_position,
                     hide_on_homepage: first.image_hide_on_homepage == 1,
+                    watermark_exempt: first.image_watermark_exempt == 1,
+                    format: first.image_format.clone(),
+                    web_filename: first.image_web_filename.clone(),
+                    display_variants: DisplayVariant::from_widths(
+                        &first.image_web_filename,
+                        &first.image_display_widths,
+                    ),
+                    thumbnail_versions: thumbnail_versions(
+                        &first.image_filename,
+                        &first.image_thumbnail_versions,
+                    ),
+                    width: first.image_width,
+                    height: first.image_height,
+                    thumb_width: first.image_thumb_width,
+                    thumb_height: first.image_thumb_height,
+                    dominant_color: first.image_dominant_color.clone(),
+                    placeholder: first.image_placeholder.clone(),
                 }
             })
             .collect();
@@ -230,6 +538,17 @@ impl Database {
               images.filename         AS image_filename, 
               images.position         AS "image_position!",
               images.hide_on_homepage AS image_hide_on_homepage,
+              images.watermark_exempt AS image_watermark_exempt,
+              images.format           AS image_format,
+              COALESCE(images.web_filename, images.filename) AS "image_web_filename!: String",
+              images.display_widths   AS image_display_widths,
+              images.thumbnail_versions AS image_thumbnail_versions,
+              images.width            AS image_width,
+              images.height           AS image_height,
+              images.thumb_width      AS image_thumb_width,
+              images.thumb_height     AS image_thumb_height,
+              images.dominant_color   AS image_dominant_color,
+              images.placeholder      AS image_placeholder,
               categories.id           AS category_id,
               categories.name         AS category_name,
               categories.position     AS "category_position!"
@@ -263,6 +582,23 @@ impl Database {
                         .collect(),
                     position: first.image_position,
                     hide_on_homepage: first.image_hide_on_homepage == 1,
+                    watermark_exempt: first.image_watermark_exempt == 1,
+                    format: first.image_format.clone(),
+                    web_filename: first.image_web_filename.clone(),
+                    display_variants: DisplayVariant::from_widths(
+                        &first.image_web_filename,
+                        &first.image_display_widths,
+                    ),
+                    thumbnail_versions: thumbnail_versions(
+                        &first.image_filename,
+                        &first.image_thumbnail_versions,
+                    ),
+                    width: first.image_width,
+                    height: first.image_height,
+                    thumb_width: first.image_thumb_width,
+                    thumb_height: first.image_thumb_height,
+                    dominant_color: first.image_dominant_color.clone(),
+                    placeholder: first.image_placeholder.clone(),
                 }
             })
             .collect();
@@ -338,6 +674,17 @@ impl Database {
               images.filename         AS image_filename, 
               images.position         AS "image_position!", 
               images.hide_on_homepage AS image_hide_on_homepage,
+              images.watermark_exempt AS image_watermark_exempt,
+              images.format           AS image_format,
+              COALESCE(images.web_filename, images.filename) AS "image_web_filename!: String",
+              images.display_widths   AS image_display_widths,
+              images.thumbnail_versions AS image_thumbnail_versions,
+              images.width            AS image_width,
+              images.height           AS image_height,
+              images.thumb_width      AS image_thumb_width,
+              images.thumb_height     AS image_thumb_height,
+              images.dominant_color   AS image_dominant_color,
+              images.placeholder      AS image_placeholder,
               categories.id           AS category_id,
               categories.name         AS category_name,
               categories.position     AS category_position
@@ -377,6 +724,23 @@ impl Database {
                         .collect(),
                     position: first.image_position,
                     hide_on_homepage: first.image_hide_on_homepage == 1,
+                    watermark_exempt: first.image_watermark_exempt == 1,
+                    format: first.image_format.clone(),
+                    web_filename: first.image_web_filename.clone(),
+                    display_variants: DisplayVariant::from_widths(
+                        &first.image_web_filename,
+                        &first.image_display_widths,
+                    ),
+                    thumbnail_versions: thumbnail_versions(
+                        &first.image_filename,
+                        &first.image_thumbnail_versions,
+                    ),
+                    width: first.image_width,
+                    height: first.image_height,
+                    thumb_width: first.image_thumb_width,
+                    thumb_height: first.image_thumb_height,
+                    dominant_color: first.image_dominant_color.clone(),
+                    placeholder: first.image_placeholder.clone(),
                 }
             })
             .collect();
@@ -422,7 +786,12 @@ impl Database {
         let user = sqlx::query_as!(
             User,
             r#"
-            SELECT username, password_hash FROM users WHERE username = ?1
+            SELECT
+              username,
+              password_hash,
+              password_login_enabled AS "password_login_enabled: bool"
+            FROM users
+            WHERE username = ?1
             "#,
             username
         )
@@ -432,6 +801,208 @@ impl Database {
         Ok(user)
     }
 
+    pub async fn update_password_hash(
+        &self,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE users SET password_hash = ?1 WHERE username = ?2",
+            password_hash,
+            username
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Sets a new password and logs out every session of the user, so anyone
+    /// who had the old one doesn't stay logged in
+    pub async fn change_password(&self, username: &str, password_hash: &str) -> Result<(), Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            "UPDATE users SET password_hash = ?1 WHERE username = ?2",
+            password_hash,
+            username
+        )
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query!("DELETE FROM sessions WHERE username = ?1", username)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn set_password_login_enabled(
+        &self,
+        username: &str,
+        enabled: bool,
+    ) -> Result<(), Error> {
+        let enabled = if enabled { 1 } else { 0 };
+
+        sqlx::query!(
+            "UPDATE users SET password_login_enabled = ?1 WHERE username = ?2",
+            enabled,
+            username
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn list_credentials_for_user(
+        &self,
+        username: &str,
+    ) -> Result<Vec<Credential>, Error> {
+        let credentials = sqlx::query_as!(
+            Credential,
+            r#"
+            SELECT id, username, name, public_key, sign_count, created_at, last_used_at
+            FROM credentials
+            WHERE username = ?1
+            ORDER BY created_at ASC
+            "#,
+            username
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(credentials)
+    }
+
+    pub async fn get_credential(&self, id: &str) -> Result<Option<Credential>, Error> {
+        let credential = sqlx::query_as!(
+            Credential,
+            r#"
+            SELECT id, username, name, public_key, sign_count, created_at, last_used_at
+            FROM credentials
+            WHERE id = ?1
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(credential)
+    }
+
+    pub async fn create_credential(
+        &self,
+        username: &str,
+        id: &str,
+        name: &str,
+        public_key: &[u8],
+        sign_count: i64,
+    ) -> Result<(), Error> {
+        let name = name.trim();
+
+        sqlx::query!(
+            r#"
+            INSERT INTO credentials (id, username, name, public_key, sign_count)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            "#,
+            id,
+            username,
+            name,
+            public_key,
+            sign_count
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn update_credential_sign_count(
+        &self,
+        id: &str,
+        sign_count: i64,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            r#"
+            UPDATE credentials
+            SET sign_count = ?1, last_used_at = CURRENT_TIMESTAMP
+            WHERE id = ?2
+            "#,
+            sign_count,
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_credential(&self, username: &str, id: &str) -> Result<(), Error> {
+        sqlx::query!(
+            "DELETE FROM credentials WHERE id = ?1 AND username = ?2",
+            id,
+            username
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn create_session(
+        &self,
+        token_hash: &str,
+        username: &str,
+        expires_at: i64,
+        now: i64,
+    ) -> Result<(), Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!("DELETE FROM sessions WHERE expires_at <= ?1", now)
+            .execute(&mut tx)
+            .await?;
+
+        sqlx::query!(
+            "INSERT INTO sessions (token_hash, username, expires_at) VALUES (?1, ?2, ?3)",
+            token_hash,
+            username,
+            expires_at
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn get_session_user(
+        &self,
+        token_hash: &str,
+        now: i64,
+    ) -> Result<Option<String>, Error> {
+        let session = sqlx::query!(
+            "SELECT username FROM sessions WHERE token_hash = ?1 AND expires_at > ?2",
+            token_hash,
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(session.map(|s| s.username))
+    }
+
+    pub async fn delete_session(&self, token_hash: &str) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM sessions WHERE token_hash = ?1", token_hash)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn create_faq(&self, faq: CreateFaq) -> Result<(), Error> {
         let question = faq.question.trim();
         let answer = faq.answer.trim();
@@ -536,6 +1107,22 @@ impl Database {
         Ok(())
     }
 
+    pub async fn set_watermark_exempt(&self, id: i64, exempt: bool) -> Result<(), Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        let exempt = if exempt { 1 } else { 0 };
+
+        sqlx::query!(
+            "UPDATE images SET watermark_exempt = ?1 WHERE id = ?2",
+            exempt,
+            id
+        )
+        .execute(&mut conn)
+        .await?;
+
+        Ok(())
+    }
+
     pub async fn move_faq(&self, id: i64, up: bool) -> Result<(), Error> {
         let mut tx = self.pool.begin().await?;
 
@@ -594,16 +1181,191 @@ impl Database {
         Ok(())
     }
 
-    pub async fn delete_image(&self, id: i64) -> Result<(), Error> {
-        let mut conn = self.pool.acquire().await?;
+    /// Deletes an image and queues its files for removal
+    pub async fn delete_image(&self, id: i64, files: &[(&str, String)]) -> Result<(), Error> {
+        let mut tx = self.pool.begin().await?;
 
         sqlx::query!("DELETE FROM images WHERE id = ?1", id)
-            .execute(&mut conn)
+            .execute(&mut tx)
+            .await?;
+
+        for (kind, filename) in files {
+            sqlx::query!(
+                "INSERT INTO orphaned_files (kind, filename) VALUES (?1, ?2)",
+                kind,
+                filename
+            )
+            .execute(&mut tx)
             .await?;
+        }
+
+        tx.commit().await?;
 
         Ok(())
     }
 
+    /// Queues files nothing refers to for removal
+    pub async fn queue_orphaned_files(&self, files: &[(&str, String)]) -> Result<(), Error> {
+        let mut tx = self.pool.begin().await?;
+
+        for (kind, filename) in files {
+            sqlx::query!(
+                "INSERT INTO orphaned_files (kind, filename) VALUES (?1, ?2)",
+                kind,
+                filename
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn list_orphaned_files(&self) -> Result<Vec<OrphanedFile>, Error> {
+        let files = sqlx::query_as!(
+            OrphanedFile,
+            "SELECT id, kind, filename FROM orphaned_files ORDER BY id ASC"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(files)
+    }
+
+    pub async fn forget_orphaned_file(&self, id: i64) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM orphaned_files WHERE id = ?1", id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn enqueue_jobs(&self, image_id: i64, tasks: &[Task]) -> Result<(), Error> {
+        let mut tx = self.pool.begin().await?;
+
+        for task in tasks {
+            insert_job(&mut tx, image_id, task).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Takes the oldest job that's due, skipping images that already have a
+    /// job running so two workers never write the same files. One statement,
+    /// so no other job for the image can be claimed in between.
+    pub async fn claim_job(&self, now: i64) -> Result<Option<Job>, Error> {
+        let row = sqlx::query_as!(
+            JobRow,
+            r#"
+            UPDATE jobs SET status = ?1
+            WHERE id = (
+                SELECT id FROM jobs
+                WHERE status = ?2
+                  AND run_after <= ?3
+                  AND image_id NOT IN (SELECT image_id FROM jobs WHERE status = ?1)
+                ORDER BY id ASC
+                LIMIT 1
+            )
+            RETURNING id AS "id!", image_id AS "image_id!", task AS "task!",
+              status AS "status!", attempts AS "attempts!", last_error
+            "#,
+            RUNNING,
+            PENDING,
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(job_from_row).transpose()
+    }
+
+    pub async fn finish_job(&self, id: i64) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM jobs WHERE id = ?1", id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records a failed attempt, leaving the job to run again after
+    /// `retry_after`, or as failed when there's no more retrying it
+    pub async fn fail_job(
+        &self,
+        id: i64,
+        error: &str,
+        retry_after: Option<i64>,
+    ) -> Result<(), Error> {
+        let status = if retry_after.is_some() {
+            PENDING
+        } else {
+            FAILED
+        };
+        let run_after = retry_after.unwrap_or_default();
+
+        sqlx::query!(
+            r#"
+            UPDATE jobs
+            SET status = ?1, attempts = attempts + 1, last_error = ?2, run_after = ?3
+            WHERE id = ?4
+            "#,
+            status,
+            error,
+            run_after,
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Starts a failed job over, with all of its attempts
+    pub async fn retry_job(&self, id: i64) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE jobs SET status = ?1, attempts = 0, run_after = 0 WHERE id = ?2 AND status = ?3",
+            PENDING,
+            id,
+            FAILED
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Puts back jobs that were running when the server stopped
+    pub async fn requeue_running_jobs(&self) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE jobs SET status = ?1 WHERE status = ?2",
+            PENDING,
+            RUNNING
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn list_jobs(&self) -> Result<Vec<Job>, Error> {
+        sqlx::query_as!(
+            JobRow,
+            r#"
+            SELECT id, image_id, task, status, attempts, last_error
+            FROM jobs
+            ORDER BY id ASC
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(job_from_row)
+        .collect()
+    }
+
     pub async fn delete_faq(&self, id: i64) -> Result<(), Error> {
         let mut conn = self.pool.acquire().await?;
 
@@ -619,3 +1381,59 @@ impl Database {
         Ok(())
     }
 }
+
+// Replaces any crop already saved for the profile
+async fn insert_thumbnail_crop(
+    conn: &mut SqliteConnection,
+    image_id: i64,
+    profile: &str,
+    rect: &Rectangle,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO thumbnail_crops (image_id, profile, x, y, width, height)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ON CONFLICT (image_id, profile)
+        DO UPDATE SET x = ?3, y = ?4, width = ?5, height = ?6
+        "#,
+        image_id,
+        profile,
+        rect.x,
+        rect.y,
+        rect.width,
+        rect.height
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+async fn insert_job(conn: &mut SqliteConnection, image_id: i64, task: &Task) -> Result<(), Error> {
+    let task = serde_json::to_string(task).unwrap();
+
+    sqlx::query!(
+        "INSERT INTO jobs (image_id, task) VALUES (?1, ?2)",
+        image_id,
+        task
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+fn job_from_row(row: JobRow) -> Result<Job, Error> {
+    let task: Task = serde_json::from_str(&row.task)
+        .map_err(|_| Error::IllegalStateError("Unreadable job task"))?;
+
+    Ok(Job {
+        id: row.id,
+        image_id: row.image_id,
+        description: task.describe(),
+        task,
+        status: row.status,
+        attempts: row.attempts,
+        last_error: row.last_error,
+    })
+}
@@ -0,0 +1,94 @@
+use axum::http::{header::ACCEPT, HeaderMap};
+use image::DynamicImage;
+use ravif::{Img, RGBA8};
+
+const WEBP_QUALITY: f32 = 80.0;
+const AVIF_QUALITY: f32 = 70.0;
+// 1 is slowest and smallest, 10 is fastest
+const AVIF_SPEED: u8 = 6;
+
+/// Smaller modern formats that thumbnails and display images are also saved
+/// in, next to the JPEG or PNG that every browser understands.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Derivative {
+    Avif,
+    WebP,
+}
+
+impl Derivative {
+    // Best first
+    pub const ALL: [Derivative; 2] = [Derivative::Avif, Derivative::WebP];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Derivative::Avif => "avif",
+            Derivative::WebP => "webp",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Derivative::Avif => "image/avif",
+            Derivative::WebP => "image/webp",
+        }
+    }
+
+    // `abc.jpg` as WebP is `abc.jpg.webp`
+    pub fn filename(self, filename: &str) -> String {
+        format!("{}.{}", filename, self.extension())
+    }
+
+    pub fn encode(self, image: &DynamicImage) -> anyhow::Result<Vec<u8>> {
+        let rgba = image.to_rgba8();
+        let (width, height) = rgba.dimensions();
+
+        match self {
+            Derivative::Avif => {
+                let pixels: Vec<RGBA8> = rgba
+                    .pixels()
+                    .map(|p| RGBA8::new(p[0], p[1], p[2], p[3]))
+                    .collect();
+
+                let encoded = ravif::Encoder::new()
+                    .with_quality(AVIF_QUALITY)
+                    .with_speed(AVIF_SPEED)
+                    .encode_rgba(Img::new(&pixels[..], width as usize, height as usize))?;
+
+                Ok(encoded.avif_file)
+            }
+            Derivative::WebP => {
+                let encoded =
+                    webp::Encoder::from_rgba(rgba.as_raw(), width, height).encode(WEBP_QUALITY);
+
+                Ok(encoded.to_vec())
+            }
+        }
+    }
+}
+
+/// The formats the client has said it accepts, best first. Browsers list
+/// these explicitly when they support them, so wildcards are ignored.
+pub fn accepted_derivatives(request_headers: &HeaderMap) -> Vec<Derivative> {
+    let accept = match request_headers.get(ACCEPT).and_then(|v| v.to_str().ok()) {
+        Some(accept) => accept,
+        None => return vec![],
+    };
+
+    let accepts = |content_type: &str| {
+        accept.split(',').any(|entry| {
+            let mut params = entry.split(';');
+            let media_type = params.next().unwrap_or_default().trim();
+            let quality = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            media_type.eq_ignore_ascii_case(content_type) && quality > 0.0
+        })
+    };
+
+    Derivative::ALL
+        .into_iter()
+        .filter(|d| accepts(d.content_type()))
+        .collect()
+}
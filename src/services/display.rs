@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use image::imageops::FilterType;
+
+use crate::model::image::display_filename;
+
+use super::{derivatives::Derivative, formats, static_files::StaticFiles, watermark::Watermark};
+
+/// The ladder of widths that images are scaled down to for display, so that
+/// small screens aren't sent the full size scan.
+#[derive(Clone)]
+pub struct DisplayVariants {
+    widths: Arc<Vec<u32>>,
+    watermark: Option<Watermark>,
+}
+
+impl DisplayVariants {
+    pub fn new(mut widths: Vec<u32>, watermark: Option<Watermark>) -> Self {
+        widths.retain(|w| *w > 0);
+        widths.sort_unstable();
+        widths.dedup();
+
+        tracing::info!("Using display widths: {:?}", widths);
+
+        DisplayVariants {
+            widths: Arc::new(widths),
+            watermark,
+        }
+    }
+
+    pub fn is_watermarked(&self) -> bool {
+        self.watermark.is_some()
+    }
+
+    /// Makes a copy of the image at each width in the ladder, returning the
+    /// widths that were made and the original's size. Images are never scaled
+    /// up, so widths past the original's are replaced by a single copy at the
+    /// original's width. Copies are watermarked, when there is one, unless the
+    /// image is exempt. Copies are named after `web_filename`.
+    pub fn make_display_variants(
+        &self,
+        filename: &str,
+        web_filename: &str,
+        watermark_exempt: bool,
+        static_files: &StaticFiles,
+    ) -> anyhow::Result<(Vec<u32>, (u32, u32))> {
+        let image_path = static_files.get_image_path(filename)?;
+
+        let (image, original_format) = formats::open(&image_path)?;
+        let format = formats::saved_format(web_filename, original_format, &image);
+
+        tracing::debug!("Successfully loaded full size image {}", filename);
+
+        let mut widths: Vec<u32> = self
+            .widths
+            .iter()
+            .copied()
+            .filter(|w| *w < image.width())
+            .collect();
+        if self.widths.iter().any(|w| *w >= image.width()) {
+            widths.push(image.width());
+        }
+
+        let mut made = vec![];
+        for width in widths {
+            tracing::debug!("Resizing image {} to {}px wide", filename, width);
+            let variant = if width == image.width() {
+                image.clone()
+            } else {
+                image.resize(width, u32::MAX, FilterType::Lanczos3)
+            };
+            let variant = match &self.watermark {
+                Some(watermark) if !watermark_exempt => watermark.apply(&variant),
+                _ => variant,
+            };
+
+            let variant_filename = display_filename(web_filename, width);
+            static_files.save_display(&variant_filename, &variant, format)?;
+
+            for derivative in Derivative::ALL {
+                tracing::debug!("Encoding {} as {:?}", variant_filename, derivative);
+                let bytes = derivative.encode(&variant)?;
+
+                static_files.save_display_derivative(&variant_filename, derivative, &bytes)?;
+            }
+
+            made.push(width);
+        }
+
+        Ok((made, (image.width(), image.height())))
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    fs::File,
+    io::{BufReader, Cursor, Read},
+    path::Path,
+};
+
+use image::{
+    codecs::{
+        bmp::BmpDecoder, gif::GifDecoder, jpeg::JpegDecoder, png::PngDecoder, tiff::TiffDecoder,
+        webp::WebPDecoder,
+    },
+    io::Reader as ImageReader,
+    ColorType, DynamicImage, ImageDecoder, ImageFormat, ImageResult,
+};
+
+use crate::model::error::Error;
+
+use super::metadata;
+
+// Every supported format can be recognised from far fewer bytes than this
+const SNIFF_LEN: u64 = 4096;
+
+/// Formats that can be uploaded, checked against the start of the file since
+/// the name the browser sends can't be trusted.
+pub fn sniff_upload_format(path: &Path) -> Result<ImageFormat, Error> {
+    let mut head = vec![];
+    File::open(path)?.take(SNIFF_LEN).read_to_end(&mut head)?;
+
+    match image::guess_format(&head) {
+        Ok(
+            format @ (ImageFormat::Jpeg
+            | ImageFormat::Png
+            | ImageFormat::WebP
+            | ImageFormat::Gif
+            | ImageFormat::Tiff
+            | ImageFormat::Bmp),
+        ) => Ok(format),
+        _ => Err(Error::UnsupportedImage),
+    }
+}
+
+pub fn extension(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Jpeg => "jpg",
+        ImageFormat::Tiff => "tif",
+        format => format.extensions_str()[0],
+    }
+}
+
+/// Name the format is stored under in the database
+pub fn name(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Jpeg => "jpeg",
+        ImageFormat::Png => "png",
+        ImageFormat::WebP => "webp",
+        ImageFormat::Gif => "gif",
+        ImageFormat::Tiff => "tiff",
+        ImageFormat::Bmp => "bmp",
+        _ => "unknown",
+    }
+}
+
+/// The content type of a format thumbnails and display copies are saved in
+pub fn content_type(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Png => "image/png",
+        _ => "image/jpeg",
+    }
+}
+
+/// The format thumbnails and display copies are saved in. Browsers can't show
+/// TIFF at all and the rest are poor choices for a scaled down painting, so
+/// anything that isn't already JPEG or PNG becomes one of them.
+pub fn web_format(original: ImageFormat, color: ColorType) -> ImageFormat {
+    match original {
+        ImageFormat::Jpeg | ImageFormat::Png => original,
+        _ if color.has_alpha() => ImageFormat::Png,
+        _ => ImageFormat::Jpeg,
+    }
+}
+
+/// The name thumbnails and display copies of an original are saved under,
+/// `abc.tif` saved as JPEG is `abc.jpg`
+pub fn web_filename(filename: &str, format: ImageFormat) -> String {
+    let stem = filename
+        .rsplit_once('.')
+        .map(|(stem, _)| stem)
+        .unwrap_or(filename);
+
+    format!("{}.{}", stem, extension(format))
+}
+
+/// The format a thumbnail or display copy named `web_filename` is saved in.
+/// Copies of images uploaded before their names were recorded share the
+/// original's name, and are in whatever format `web_format` picks for it.
+pub fn saved_format(
+    web_filename: &str,
+    original: ImageFormat,
+    image: &DynamicImage,
+) -> ImageFormat {
+    match ImageFormat::from_path(web_filename) {
+        Ok(format @ (ImageFormat::Jpeg | ImageFormat::Png)) => format,
+        _ => web_format(original, image.color()),
+    }
+}
+
+/// An upload's colour type, read from its header without decoding it. WebP is
+/// the exception, the decoder reads the whole image up front.
+pub fn color_type(path: &Path, format: ImageFormat) -> Result<ColorType, Error> {
+    let reader = BufReader::new(File::open(path)?);
+
+    let color = match format {
+        ImageFormat::Jpeg => JpegDecoder::new(reader)?.color_type(),
+        ImageFormat::Png => PngDecoder::new(reader)?.color_type(),
+        ImageFormat::WebP => WebPDecoder::new(reader)?.color_type(),
+        ImageFormat::Gif => GifDecoder::new(reader)?.color_type(),
+        ImageFormat::Tiff => TiffDecoder::new(reader)?.color_type(),
+        ImageFormat::Bmp => BmpDecoder::new(reader)?.color_type(),
+        _ => return Err(Error::UnsupportedImage),
+    };
+
+    Ok(color)
+}
+
+/// Opens an image by its contents rather than its extension, turned the way
+/// its EXIF orientation says. For GIFs this is the first frame.
+pub fn open(path: &Path) -> anyhow::Result<(DynamicImage, ImageFormat)> {
+    let reader = ImageReader::open(path)?.with_guessed_format()?;
+    let format = reader
+        .format()
+        .ok_or_else(|| anyhow::anyhow!("Unrecognised image format for {}", path.display()))?;
+
+    let image = metadata::apply_orientation(reader.decode()?, metadata::orientation(path));
+
+    Ok((image, format))
+}
+
+/// An image's size read from its header, turned the way `open` turns it
+pub fn dimensions(path: &Path) -> Result<(u32, u32), Error> {
+    let (width, height) = ImageReader::open(path)?
+        .with_guessed_format()?
+        .into_dimensions()?;
+
+    match metadata::orientation(path) {
+        5..=8 => Ok((height, width)),
+        _ => Ok((width, height)),
+    }
+}
+
+/// Saves a thumbnail or display copy. JPEG has no alpha channel or 16 bit
+/// colour, which scans and PNGs with transparency can have.
+pub fn save(image: &DynamicImage, path: &Path, format: ImageFormat) -> ImageResult<()> {
+    match format {
+        ImageFormat::Jpeg => {
+            DynamicImage::ImageRgb8(image.to_rgb8()).save_with_format(path, format)
+        }
+        _ => image.save_with_format(path, format),
+    }
+}
+
+/// Encodes a thumbnail or display copy the way `save` would write it
+pub fn encode(image: &DynamicImage, format: ImageFormat) -> ImageResult<Vec<u8>> {
+    let mut bytes = Cursor::new(vec![]);
+    match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8()).write_to(&mut bytes, format),
+        _ => image.write_to(&mut bytes, format),
+    }?;
+
+    Ok(bytes.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbaImage;
+
+    use super::*;
+
+    #[test]
+    fn names_copies_by_the_format_they_are_saved_in() {
+        assert_eq!(web_filename("abc.tif", ImageFormat::Jpeg), "abc.jpg");
+        assert_eq!(web_filename("abc.gif", ImageFormat::Png), "abc.png");
+        assert_eq!(web_filename("abc.jpg", ImageFormat::Jpeg), "abc.jpg");
+        assert_eq!(web_filename("abc", ImageFormat::Jpeg), "abc.jpg");
+    }
+
+    #[test]
+    fn keeps_alpha_in_copies() {
+        assert_eq!(
+            web_format(ImageFormat::Tiff, ColorType::Rgb16),
+            ImageFormat::Jpeg
+        );
+        assert_eq!(
+            web_format(ImageFormat::WebP, ColorType::Rgba8),
+            ImageFormat::Png
+        );
+        assert_eq!(
+            web_format(ImageFormat::Png, ColorType::Rgb8),
+            ImageFormat::Png
+        );
+        assert_eq!(
+            web_format(ImageFormat::Jpeg, ColorType::L8),
+            ImageFormat::Jpeg
+        );
+    }
+
+    #[test]
+    fn saves_copies_in_the_format_they_are_named_for() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(1, 1));
+
+        assert_eq!(
+            saved_format("abc.jpg", ImageFormat::Tiff, &image),
+            ImageFormat::Jpeg
+        );
+        assert_eq!(
+            saved_format("abc.png", ImageFormat::Tiff, &image),
+            ImageFormat::Png
+        );
+        // Named like the original, from before names were recorded
+        assert_eq!(
+            saved_format("abc.tif", ImageFormat::Tiff, &image),
+            ImageFormat::Png
+        );
+    }
+}
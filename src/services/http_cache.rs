@@ -0,0 +1,294 @@
+use std::time::SystemTime;
+
+use axum::{
+    body::{self, Empty, Full},
+    headers::{ETag, HeaderMapExt, IfModifiedSince, IfNoneMatch, LastModified},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, VARY},
+        HeaderMap, HeaderValue, Method, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+
+/// Uploaded images are named with a fresh UUID and never overwritten.
+pub const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// Files that keep their name when they change, such as thumbnails asked for
+/// without a version or a stylesheet after a deploy.
+pub const REVALIDATE: &str = "public, no-cache";
+/// Public pages change whenever something is edited in the admin pages, so
+/// they are only reused briefly before being checked again.
+pub const HTML: &str = "public, max-age=60, must-revalidate";
+pub const NO_STORE: &str = "no-store";
+
+/// True when the client's cached copy is still current. `If-None-Match` wins
+/// over `If-Modified-Since` when both are sent.
+pub fn is_not_modified(
+    request_headers: &HeaderMap,
+    etag: &ETag,
+    last_modified: Option<SystemTime>,
+) -> bool {
+    if let Some(if_none_match) = request_headers.typed_get::<IfNoneMatch>() {
+        return !if_none_match.precondition_passes(etag);
+    }
+
+    match (
+        request_headers.typed_get::<IfModifiedSince>(),
+        last_modified,
+    ) {
+        (Some(if_modified_since), Some(last_modified)) => {
+            !if_modified_since.is_modified(last_modified)
+        }
+        _ => false,
+    }
+}
+
+pub fn validator_headers(
+    etag: ETag,
+    last_modified: Option<SystemTime>,
+    cache_control: &'static str,
+) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    headers.typed_insert(etag);
+    if let Some(last_modified) = last_modified {
+        headers.typed_insert(LastModified::from(last_modified));
+    }
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static(cache_control));
+
+    headers
+}
+
+pub fn not_modified(headers: HeaderMap) -> Response {
+    (StatusCode::NOT_MODIFIED, headers).into_response()
+}
+
+/// Middleware for the public pages. Rendered HTML has no modification time to
+/// go on so the ETag is a hash of the body, which at least saves sending the
+/// page again when nothing has changed. It's weak because the page may be
+/// compressed on the way out, and all encodings of it share the one tag.
+pub async fn cache_html<B>(req: Request<B>, next: Next<B>) -> Response {
+    let is_cacheable_method = matches!(*req.method(), Method::GET | Method::HEAD);
+    let request_headers = req.headers().clone();
+
+    let res = next.run(req).await;
+
+    let is_html = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with("text/html"))
+        .unwrap_or(false);
+
+    if !is_cacheable_method
+        || res.status() != StatusCode::OK
+        || !is_html
+        || res.headers().contains_key(CACHE_CONTROL)
+    {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!("Failed to buffer page: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let etag = body_etag(&bytes);
+    let is_not_modified = is_not_modified(&request_headers, &etag, None);
+
+    parts.headers.extend(validator_headers(etag, None, HTML));
+    parts
+        .headers
+        .insert(VARY, HeaderValue::from_static("Accept-Encoding"));
+
+    if is_not_modified {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(CONTENT_LENGTH);
+        return Response::from_parts(parts, body::boxed(Empty::new()));
+    }
+
+    Response::from_parts(parts, body::boxed(Full::from(bytes)))
+}
+
+/// Middleware for the admin pages, which should never be kept by the browser
+/// or anything in between.
+pub async fn no_store<B>(req: Request<B>, next: Next<B>) -> Response {
+    let mut res = next.run(req).await;
+
+    res.headers_mut()
+        .entry(CACHE_CONTROL)
+        .or_insert(HeaderValue::from_static(NO_STORE));
+
+    res
+}
+
+fn body_etag(body: &[u8]) -> ETag {
+    let hash = base64::encode_config(Sha256::digest(body), base64::URL_SAFE_NO_PAD);
+
+    format!("W/\"{}\"", hash).parse().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use axum::{
+        body::Body,
+        http::header::{ETAG, LAST_MODIFIED},
+        middleware,
+        response::Html,
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn headers(headers: &[(&'static str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+
+        map
+    }
+
+    fn etag() -> ETag {
+        "\"abc\"".parse().unwrap()
+    }
+
+    fn modified() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_000_000)
+    }
+
+    fn http_date(time: SystemTime) -> String {
+        let mut map = HeaderMap::new();
+        map.typed_insert(LastModified::from(time));
+
+        map[LAST_MODIFIED].to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn matches_if_none_match() {
+        let etag = etag();
+
+        assert!(is_not_modified(
+            &headers(&[("if-none-match", "\"abc\"")]),
+            &etag,
+            None
+        ));
+        assert!(is_not_modified(
+            &headers(&[("if-none-match", "\"other\", W/\"abc\"")]),
+            &etag,
+            None
+        ));
+        assert!(is_not_modified(
+            &headers(&[("if-none-match", "*")]),
+            &etag,
+            None
+        ));
+        assert!(!is_not_modified(
+            &headers(&[("if-none-match", "\"other\"")]),
+            &etag,
+            None
+        ));
+        assert!(!is_not_modified(&headers(&[]), &etag, Some(modified())));
+    }
+
+    #[test]
+    fn matches_if_modified_since() {
+        let etag = etag();
+        let date = http_date(modified());
+
+        assert!(is_not_modified(
+            &headers(&[("if-modified-since", &date)]),
+            &etag,
+            Some(modified())
+        ));
+        assert!(!is_not_modified(
+            &headers(&[("if-modified-since", &date)]),
+            &etag,
+            Some(modified() + Duration::from_secs(60))
+        ));
+        // Nothing to compare the date with
+        assert!(!is_not_modified(
+            &headers(&[("if-modified-since", &date)]),
+            &etag,
+            None
+        ));
+    }
+
+    #[test]
+    fn prefers_if_none_match_over_if_modified_since() {
+        let etag = etag();
+        let date = http_date(modified());
+
+        assert!(!is_not_modified(
+            &headers(&[("if-none-match", "\"other\""), ("if-modified-since", &date)]),
+            &etag,
+            Some(modified())
+        ));
+        assert!(is_not_modified(
+            &headers(&[
+                ("if-none-match", "\"abc\""),
+                ("if-modified-since", &http_date(UNIX_EPOCH))
+            ]),
+            &etag,
+            Some(modified())
+        ));
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route("/", get(|| async { Html("<p>page</p>") }))
+            .route("/text", get(|| async { "text" }))
+            .layer(middleware::from_fn(cache_html))
+    }
+
+    async fn request(uri: &str, headers: &[(&'static str, &str)]) -> Response {
+        let mut req = Request::get(uri).body(Body::empty()).unwrap();
+        req.headers_mut().extend(self::headers(headers));
+
+        app().oneshot(req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn tags_html_pages() {
+        let res = request("/", &[]).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CACHE_CONTROL], HTML);
+        assert_eq!(res.headers()[VARY], "Accept-Encoding");
+        let etag = res.headers()[ETAG].to_str().unwrap();
+        assert!(etag.starts_with("W/\""));
+    }
+
+    #[tokio::test]
+    async fn answers_not_modified_for_the_same_page() {
+        let res = request("/", &[]).await;
+        let etag = res.headers()[ETAG].to_str().unwrap().to_string();
+
+        let res = request("/", &[("if-none-match", &etag)]).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert!(!res.headers().contains_key(CONTENT_LENGTH));
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(body.is_empty());
+
+        let res = request("/", &[("if-none-match", "W/\"other\"")]).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn leaves_other_responses_alone() {
+        let res = request("/text", &[]).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res.headers().contains_key(CACHE_CONTROL));
+        assert!(!res.headers().contains_key(ETAG));
+    }
+}
@@ -0,0 +1,64 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ipnet::IpNet;
+
+/// Networks allowed to reach the admin routes. Checked against the address of
+/// the TCP peer, so behind a reverse proxy this will be the proxy's address.
+#[derive(Clone)]
+pub struct IpAllowlist {
+    networks: Arc<Vec<IpNet>>,
+}
+
+impl IpAllowlist {
+    pub fn new(networks: Vec<IpNet>) -> Self {
+        for network in &networks {
+            tracing::info!("Allowing admin access from: {}", network);
+        }
+
+        IpAllowlist {
+            networks: Arc::new(networks),
+        }
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        // IPv4 clients on a dual stack socket show up as IPv4-mapped IPv6
+        let ip = ip.to_canonical();
+        self.networks.iter().any(|network| network.contains(&ip))
+    }
+
+    pub async fn check<B>(self, req: Request<B>, next: Next<B>) -> Response {
+        let peer = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+
+        match peer {
+            Some(ip) if self.allows(ip) => next.run(req).await,
+            _ => {
+                tracing::warn!(
+                    "Rejected admin request for {} from {:?}",
+                    req.uri().path(),
+                    peer
+                );
+                (StatusCode::FORBIDDEN, "Forbidden").into_response()
+            }
+        }
+    }
+}
+
+// Accepts CIDR notation or a bare address, which is treated as a single host
+pub fn parse_network(value: &str) -> Result<IpNet, String> {
+    value
+        .parse::<IpNet>()
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("`{}` is not an IP address or CIDR network", value))
+}
@@ -0,0 +1,308 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::watch;
+
+use crate::model::{
+    error::Error,
+    image::display_filename,
+    job::{Job, Task},
+};
+
+use super::{
+    auth::now,
+    cleanup::{remove_orphaned_files, DISPLAY, THUMB},
+    database::Database,
+    display::DisplayVariants,
+    static_files::StaticFiles,
+    thumbs::{make_thumbnail, make_thumbnail_derivatives, ThumbnailProfiles, DEFAULT_PROFILE},
+};
+
+// Attempts a job gets before it's left for an admin to retry
+const MAX_ATTEMPTS: i64 = 5;
+// Seconds before the first retry, doubling with each attempt after
+const RETRY_DELAY: i64 = 30;
+// Workers check for jobs this often even when they haven't been woken, so
+// retries come due without anything being enqueued
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Image processing done in the background on blocking threads, so decoding
+/// and resizing large scans doesn't hold up requests. Jobs are kept in the
+/// database, so they're picked up again after a restart.
+#[derive(Clone)]
+pub struct JobQueue {
+    db: Database,
+    thumbnail_profiles: ThumbnailProfiles,
+    wake: Arc<watch::Sender<()>>,
+}
+
+impl JobQueue {
+    pub async fn start(
+        workers: usize,
+        db: Database,
+        static_files: StaticFiles,
+        display_variants: DisplayVariants,
+        thumbnail_profiles: ThumbnailProfiles,
+    ) -> Result<Self, Error> {
+        db.requeue_running_jobs().await?;
+
+        let (wake, woken) = watch::channel(());
+
+        tracing::info!("Starting {} image processing workers", workers);
+        for _ in 0..workers.max(1) {
+            let worker = Worker {
+                db: db.clone(),
+                static_files: static_files.clone(),
+                display_variants: display_variants.clone(),
+                thumbnail_profiles: thumbnail_profiles.clone(),
+                woken: woken.clone(),
+            };
+            tokio::spawn(worker.work());
+        }
+
+        Ok(JobQueue {
+            db,
+            thumbnail_profiles,
+            wake: Arc::new(wake),
+        })
+    }
+
+    /// Everything made from a newly uploaded file
+    pub fn new_image_tasks(&self) -> Vec<Task> {
+        let mut tasks = vec![Task::Thumbnail {
+            profile: DEFAULT_PROFILE.to_owned(),
+        }];
+        tasks.extend(self.named_thumbnail_tasks());
+        tasks.push(Task::DisplayVariants);
+
+        tasks
+    }
+
+    /// Remakes an image's files after the display widths or thumbnail profiles
+    /// have changed, each thumbnail with its saved crop. A default thumbnail
+    /// without a saved crop may have been cropped before crops were saved, so
+    /// it's kept and only gets any modern format copies it's missing.
+    pub fn regenerate_tasks(&self, has_default_crop: bool) -> Vec<Task> {
+        let mut tasks = vec![Task::DisplayVariants];
+        if has_default_crop {
+            tasks.push(Task::Thumbnail {
+                profile: DEFAULT_PROFILE.to_owned(),
+            });
+        } else {
+            tasks.push(Task::ThumbnailDerivatives);
+        }
+        tasks.extend(self.named_thumbnail_tasks());
+
+        tasks
+    }
+
+    fn named_thumbnail_tasks(&self) -> impl Iterator<Item = Task> + '_ {
+        self.thumbnail_profiles.named().map(|p| Task::Thumbnail {
+            profile: p.name.clone(),
+        })
+    }
+
+    pub async fn enqueue(&self, image_id: i64, tasks: &[Task]) -> Result<(), Error> {
+        self.db.enqueue_jobs(image_id, tasks).await?;
+        self.wake();
+
+        Ok(())
+    }
+
+    pub async fn retry(&self, job_id: i64) -> Result<(), Error> {
+        self.db.retry_job(job_id).await?;
+        self.wake();
+
+        Ok(())
+    }
+
+    /// Lets the workers know there are new jobs, for jobs queued along with
+    /// other changes to an image
+    pub fn wake(&self) {
+        // Only fails when there are no workers left to wake
+        let _ = self.wake.send(());
+    }
+}
+
+struct Worker {
+    db: Database,
+    static_files: StaticFiles,
+    display_variants: DisplayVariants,
+    thumbnail_profiles: ThumbnailProfiles,
+    woken: watch::Receiver<()>,
+}
+
+impl Worker {
+    async fn work(mut self) {
+        loop {
+            match self.db.claim_job(now()).await {
+                Ok(Some(job)) => self.run(job).await,
+                Ok(None) => {
+                    // A wake since the last one, even while claiming, returns
+                    // straight away so no job is missed
+                    let _ = tokio::time::timeout(POLL_INTERVAL, self.woken.changed()).await;
+                }
+                Err(e) => {
+                    tracing::error!("Failed to take a job: {}", e);
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    async fn run(&self, job: Job) {
+        tracing::debug!("{} for image {}", job.description, job.image_id);
+
+        let finished = match self.process(&job).await {
+            Ok(()) => self.db.finish_job(job.id).await,
+            Err(e) => {
+                let attempts = job.attempts + 1;
+                let retry_after = if attempts < MAX_ATTEMPTS {
+                    Some(now() + RETRY_DELAY * 2_i64.pow(attempts as u32 - 1))
+                } else {
+                    None
+                };
+
+                tracing::error!(
+                    "{} for image {} failed, attempt {} of {}: {}",
+                    job.description,
+                    job.image_id,
+                    attempts,
+                    MAX_ATTEMPTS,
+                    e
+                );
+
+                self.db.fail_job(job.id, &e.to_string(), retry_after).await
+            }
+        };
+
+        if let Err(e) = finished {
+            tracing::error!("Failed to update job {}: {}", job.id, e);
+        }
+    }
+
+    async fn process(&self, job: &Job) -> anyhow::Result<()> {
+        // Deleted images take their jobs with them
+        let Some(image) = self.db.get_image_by_id(job.image_id).await? else {
+            return Ok(());
+        };
+
+        let filename = image.filename.clone();
+        let web_filename = image.web_filename.clone();
+        let static_files = self.static_files.clone();
+
+        // Whether the image still has the file these were made from, and
+        // what was made
+        let (current, made_files) = match &job.task {
+            Task::Thumbnail { profile } => {
+                // Left behind by a profile that's since been removed
+                let Some(profile) = self.thumbnail_profiles.get(profile).cloned() else {
+                    tracing::warn!("Skipping thumbnail for unknown profile {}", profile);
+                    return Ok(());
+                };
+
+                let crop_rect = self.db.get_thumbnail_crop(image.id, &profile.name).await?;
+                let name = profile.name.clone();
+                let made = tokio::task::spawn_blocking(move || {
+                    make_thumbnail(&filename, &web_filename, &profile, crop_rect, &static_files)
+                })
+                .await??;
+
+                let mut current = self
+                    .db
+                    .set_thumbnail_version(image.id, &image.filename, &name, &made.version)
+                    .await?;
+                // The grid shows the default thumbnail
+                if current && name == DEFAULT_PROFILE {
+                    current = self
+                        .db
+                        .set_thumbnail_placeholder(image.id, &image.filename, &made.placeholder)
+                        .await?;
+                }
+
+                (current, vec![(THUMB, image.web_filename.clone())])
+            }
+            Task::ThumbnailDerivatives => {
+                let made = tokio::task::spawn_blocking(move || {
+                    make_thumbnail_derivatives(&web_filename, &static_files)
+                })
+                .await??;
+
+                let mut current = self
+                    .db
+                    .set_thumbnail_version(
+                        image.id,
+                        &image.filename,
+                        DEFAULT_PROFILE,
+                        &made.version,
+                    )
+                    .await?;
+                if current {
+                    current = self
+                        .db
+                        .set_thumbnail_placeholder(image.id, &image.filename, &made.placeholder)
+                        .await?;
+                }
+
+                (current, vec![(THUMB, image.web_filename.clone())])
+            }
+            Task::DisplayVariants => {
+                let display_variants = self.display_variants.clone();
+                let exempt = image.watermark_exempt;
+                let (widths, (width, height)) = tokio::task::spawn_blocking(move || {
+                    display_variants.make_display_variants(
+                        &filename,
+                        &web_filename,
+                        exempt,
+                        &static_files,
+                    )
+                })
+                .await??;
+
+                let mut current = self
+                    .db
+                    .set_display_widths(image.id, &image.filename, &widths)
+                    .await?;
+                if current {
+                    current = self
+                        .db
+                        .set_image_size(image.id, &image.filename, width, height)
+                        .await?;
+                }
+
+                // Copies at widths that have since been dropped from the ladder
+                if current {
+                    for old in image
+                        .display_variants
+                        .iter()
+                        .filter(|v| !widths.contains(&v.width))
+                    {
+                        if let Err(e) = self.static_files.delete_display(&old.filename).await {
+                            tracing::warn!("Failed to delete {}: {}", old.filename, e);
+                        }
+                    }
+                }
+
+                let made_files = widths
+                    .iter()
+                    .map(|w| (DISPLAY, display_filename(&image.web_filename, *w)))
+                    .collect();
+
+                (current, made_files)
+            }
+        };
+
+        // The image was replaced or deleted while this ran, after its files
+        // were queued for removal, so what was just made goes the same way
+        if !current {
+            tracing::debug!(
+                "{} is no longer used by image {}, removing what was made from it",
+                image.filename,
+                image.id
+            );
+            self.db.queue_orphaned_files(&made_files).await?;
+            remove_orphaned_files(&self.db, &self.static_files).await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1157 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, Cursor, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use clap::ValueEnum;
+use exif::{experimental::Writer, Context, Field, In, Reader, Tag, Value};
+use flate2::Crc;
+use image::{DynamicImage, ImageFormat};
+
+use crate::model::error::Error;
+
+/// What is removed from the metadata of uploads before the original is
+/// stored. Thumbnails and display copies never carry any metadata.
+///
+/// GIF has no EXIF, so all there is to remove is XMP and other application
+/// data, and comments when removing camera details. BMP has nowhere to keep
+/// metadata at all.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum MetadataPolicy {
+    /// Store originals exactly as they were uploaded
+    Keep,
+    /// Remove GPS coordinates
+    Location,
+    /// Remove everything except the artist, copyright and orientation
+    LocationAndCamera,
+}
+
+const JPEG_SOI: [u8; 2] = [0xff, 0xd8];
+const JPEG_SOS: u8 = 0xda;
+const JPEG_APP1: u8 = 0xe1;
+const JPEG_APP13: u8 = 0xed;
+const JPEG_EXIF_PREFIX: &[u8] = b"Exif\0\0";
+const JPEG_XMP_PREFIX: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const JPEG_XMP_EXTENSION_PREFIX: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp\0";
+
+const GIF_IMAGE: u8 = 0x2c;
+const GIF_EXTENSION: u8 = 0x21;
+const GIF_TRAILER: u8 = 0x3b;
+const GIF_PLAIN_TEXT: u8 = 0x01;
+const GIF_GRAPHIC_CONTROL: u8 = 0xf9;
+const GIF_COMMENT: u8 = 0xfe;
+const GIF_APPLICATION: u8 = 0xff;
+// Looping and the colour profile, everything else (XMP included) is dropped
+const GIF_KEPT_APPLICATIONS: &[&[u8]] = &[b"NETSCAPE2.0", b"ANIMEXTS1.0", b"ICCRGBG1012"];
+
+// EXIF in PNG and WebP can be as large as the file, anything bigger than this
+// is dropped rather than read into memory to be rewritten
+const MAX_EXIF_LEN: u64 = 1024 * 1024;
+
+// VP8X feature flags, see https://developers.google.com/speed/webp/docs/riff_container
+const WEBP_FLAG_EXIF: u8 = 0x08;
+const WEBP_FLAG_XMP: u8 = 0x04;
+
+/// Copies an upload to where its original is kept, removing the metadata the
+/// policy asks for. JPEG, PNG, WebP and GIF are rewritten a segment at a time
+/// and TIFF tags are removed in place, all without decoding the image. XMP can
+/// hold anything, including GPS coordinates, so it's always dropped when
+/// stripping. Uploads that can't be taken apart are rejected rather than
+/// stored with whatever metadata they have.
+pub fn strip(
+    from: &Path,
+    to: &Path,
+    format: ImageFormat,
+    policy: MetadataPolicy,
+) -> Result<(), Error> {
+    let stripped = match (policy, format) {
+        (MetadataPolicy::Keep, _) | (_, ImageFormat::Bmp) => {
+            std::fs::copy(from, to)?;
+            return Ok(());
+        }
+        (_, ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP | ImageFormat::Gif) => {
+            let mut input = BufReader::new(File::open(from)?);
+            let mut output = BufWriter::new(File::create(to)?);
+
+            let stripped = match format {
+                ImageFormat::Jpeg => strip_jpeg(&mut input, &mut output, policy),
+                ImageFormat::Png => strip_png(&mut input, &mut output, policy),
+                ImageFormat::WebP => strip_webp(&mut input, &mut output, policy),
+                _ => strip_gif(&mut input, &mut output, policy),
+            };
+
+            stripped.and_then(|_| output.flush())
+        }
+        (_, ImageFormat::Tiff) => {
+            std::fs::copy(from, to)?;
+            let mut file = OpenOptions::new().read(true).write(true).open(to)?;
+
+            strip_tiff(&mut file, policy)
+        }
+        // Uploads are only accepted in the formats above
+        _ => return Err(Error::UnsupportedImage),
+    };
+
+    if let Err(e) = stripped {
+        let _ = std::fs::remove_file(to);
+
+        return Err(match e.kind() {
+            ErrorKind::InvalidData | ErrorKind::UnexpectedEof => {
+                tracing::warn!("Malformed {:?} container: {}", format, e);
+                Error::MalformedImage
+            }
+            _ => e.into(),
+        });
+    }
+
+    Ok(())
+}
+
+/// The EXIF orientation of an image file, 1 when there isn't one
+pub fn orientation(path: &Path) -> u32 {
+    let exif = std::fs::File::open(path).ok().and_then(|file| {
+        Reader::new()
+            .read_from_container(&mut BufReader::new(file))
+            .ok()
+    });
+
+    exif.and_then(|exif| {
+        exif.get_field(Tag::Orientation, In::PRIMARY)
+            .and_then(|field| field.value.get_uint(0))
+    })
+    .unwrap_or(1)
+}
+
+/// Turns decoded pixels the right way up. Decoders ignore the orientation tag
+/// but browsers don't, so without this thumbnails of phone photos come out on
+/// their side.
+pub fn apply_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+// Rewrites a raw EXIF block (a TIFF structure) keeping only what the policy
+// allows. `None` means nothing is left worth keeping.
+fn strip_exif(raw: &[u8], policy: MetadataPolicy) -> Option<Vec<u8>> {
+    let exif = match Reader::new().read_raw(raw.to_vec()) {
+        Ok(exif) => exif,
+        Err(e) => {
+            // Can't tell what's in it, so it can't be kept
+            tracing::warn!("Dropping unreadable EXIF: {}", e);
+            return None;
+        }
+    };
+
+    let keep = |field: &Field| {
+        // Offsets inside maker notes point at where they used to be, and the
+        // embedded thumbnail is of the unstripped, unrotated image.
+        let is_restructured = field.ifd_num != In::PRIMARY
+            || field.tag == Tag::MakerNote
+            || matches!(field.value, Value::Unknown(..));
+
+        match policy {
+            _ if is_restructured => false,
+            MetadataPolicy::Keep => true,
+            MetadataPolicy::Location => field.tag.context() != Context::Gps,
+            MetadataPolicy::LocationAndCamera => {
+                matches!(field.tag, Tag::Artist | Tag::Copyright | Tag::Orientation)
+            }
+        }
+    };
+
+    let fields: Vec<&Field> = exif.fields().filter(|f| keep(f)).collect();
+    if fields.is_empty() {
+        return None;
+    }
+
+    let mut writer = Writer::new();
+    for field in fields {
+        writer.push_field(field);
+    }
+
+    let mut out = Cursor::new(vec![]);
+    match writer.write(&mut out, exif.little_endian()) {
+        Ok(()) => Some(out.into_inner()),
+        Err(e) => {
+            tracing::warn!("Dropping EXIF that couldn't be rewritten: {}", e);
+            None
+        }
+    }
+}
+
+fn strip_jpeg(
+    input: &mut impl Read,
+    output: &mut impl Write,
+    policy: MetadataPolicy,
+) -> io::Result<()> {
+    let mut soi = [0u8; 2];
+    input.read_exact(&mut soi)?;
+    if soi != JPEG_SOI {
+        return Err(malformed());
+    }
+    output.write_all(&JPEG_SOI)?;
+
+    loop {
+        let mut marker = [0u8; 2];
+        input.read_exact(&mut marker)?;
+        // Markers can be padded with any number of fill bytes
+        while marker == [0xff, 0xff] {
+            input.read_exact(&mut marker[1..])?;
+        }
+        if marker[0] != 0xff {
+            return Err(malformed());
+        }
+
+        // Everything from the start of scan on is image data
+        if marker[1] == JPEG_SOS {
+            output.write_all(&marker)?;
+            io::copy(input, output)?;
+            return Ok(());
+        }
+
+        let mut len = [0u8; 2];
+        input.read_exact(&mut len)?;
+        let len = u16::from_be_bytes(len) as usize;
+        if len < 2 {
+            return Err(malformed());
+        }
+        let mut data = vec![0u8; len - 2];
+        input.read_exact(&mut data)?;
+
+        match marker[1] {
+            JPEG_APP1 if data.starts_with(JPEG_EXIF_PREFIX) => {
+                if let Some(exif) = strip_exif(&data[JPEG_EXIF_PREFIX.len()..], policy) {
+                    let len = 2 + JPEG_EXIF_PREFIX.len() + exif.len();
+                    if len > u16::MAX as usize {
+                        return Err(malformed());
+                    }
+
+                    output.write_all(&[0xff, JPEG_APP1])?;
+                    output.write_all(&(len as u16).to_be_bytes())?;
+                    output.write_all(JPEG_EXIF_PREFIX)?;
+                    output.write_all(&exif)?;
+                }
+            }
+            JPEG_APP1
+                if data.starts_with(JPEG_XMP_PREFIX)
+                    || data.starts_with(JPEG_XMP_EXTENSION_PREFIX) => {}
+            // Photoshop's IPTC block, which has places and camera details too
+            JPEG_APP13 if matches!(policy, MetadataPolicy::LocationAndCamera) => {}
+            // Everything else, including the colour profile, is kept
+            _ => {
+                output.write_all(&marker)?;
+                output.write_all(&((len as u16).to_be_bytes()))?;
+                output.write_all(&data)?;
+            }
+        }
+    }
+}
+
+fn strip_png(
+    input: &mut impl Read,
+    output: &mut impl Write,
+    policy: MetadataPolicy,
+) -> io::Result<()> {
+    let mut signature = [0u8; PNG_SIGNATURE.len()];
+    input.read_exact(&mut signature)?;
+    if signature != PNG_SIGNATURE {
+        return Err(malformed());
+    }
+    output.write_all(&PNG_SIGNATURE)?;
+
+    // Length and type, the data follows and then a CRC
+    let mut header = [0u8; 8];
+    while read_header(input, &mut header)? {
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let kind = &header[4..8];
+
+        match kind {
+            b"eXIf" if len <= MAX_EXIF_LEN => {
+                let mut data = vec![0u8; len as usize];
+                input.read_exact(&mut data)?;
+                skip(input, 4)?;
+
+                if let Some(exif) = strip_exif(&data, policy) {
+                    write_png_chunk(output, b"eXIf", &exif)?;
+                }
+            }
+            // Too big to be anything worth keeping
+            b"eXIf" => skip(input, len + 4)?,
+            b"iTXt" => {
+                // Only the keyword is needed to tell if it's XMP
+                let mut keyword = vec![0u8; len.min(PNG_XMP_KEYWORD.len() as u64) as usize];
+                input.read_exact(&mut keyword)?;
+                let rest = len - keyword.len() as u64 + 4;
+
+                if keyword == PNG_XMP_KEYWORD {
+                    skip(input, rest)?;
+                } else {
+                    output.write_all(&header)?;
+                    output.write_all(&keyword)?;
+                    copy_exact(input, output, rest)?;
+                }
+            }
+            _ => {
+                output.write_all(&header)?;
+                copy_exact(input, output, len + 4)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_png_chunk(output: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+
+    output.write_all(&(data.len() as u32).to_be_bytes())?;
+    output.write_all(kind)?;
+    output.write_all(data)?;
+    output.write_all(&crc.sum().to_be_bytes())
+}
+
+// The RIFF size and the VP8X flags describe chunks that come after them, so
+// they're filled in once everything has been written
+fn strip_webp(
+    input: &mut impl Read,
+    output: &mut (impl Write + Seek),
+    policy: MetadataPolicy,
+) -> io::Result<()> {
+    let mut header = [0u8; 12];
+    input.read_exact(&mut header)?;
+    if &header[..4] != b"RIFF" || &header[8..12] != b"WEBP" {
+        return Err(malformed());
+    }
+    output.write_all(b"RIFF\0\0\0\0WEBP")?;
+
+    let mut has_exif = false;
+    // Where the VP8X flags were written, and what they were
+    let mut vp8x_flags: Option<(u64, u8)> = None;
+
+    let mut chunk_header = [0u8; 8];
+    while read_header(input, &mut chunk_header)? {
+        let kind: [u8; 4] = chunk_header[..4].try_into().unwrap();
+        let len = u32::from_le_bytes(chunk_header[4..8].try_into().unwrap()) as u64;
+
+        match &kind {
+            b"EXIF" if len <= MAX_EXIF_LEN => {
+                let mut data = vec![0u8; len as usize];
+                input.read_exact(&mut data)?;
+
+                // Some writers include the JPEG style prefix, some don't
+                let raw = data.strip_prefix(JPEG_EXIF_PREFIX).unwrap_or(&data);
+                if let Some(exif) = strip_exif(raw, policy) {
+                    write_webp_chunk(output, &kind, &exif)?;
+                    has_exif = true;
+                }
+            }
+            b"EXIF" | b"XMP " => skip(input, len)?,
+            b"VP8X" if len > 0 => {
+                let flags_at = output.stream_position()? + 8;
+                let mut data = vec![0u8; len as usize];
+                input.read_exact(&mut data)?;
+
+                vp8x_flags = Some((flags_at, data[0]));
+                write_webp_chunk(output, &kind, &data)?;
+            }
+            _ => {
+                output.write_all(&chunk_header)?;
+                copy_exact(input, output, len)?;
+                if len & 1 == 1 {
+                    output.write_all(&[0])?;
+                }
+            }
+        }
+
+        // Chunks are padded to an even length, which the last one may leave out
+        if len & 1 == 1 {
+            let _ = input.read(&mut [0u8; 1])?;
+        }
+    }
+
+    let end = output.stream_position()?;
+
+    if let Some((flags_at, mut flags)) = vp8x_flags {
+        flags &= !WEBP_FLAG_XMP;
+        if !has_exif {
+            flags &= !WEBP_FLAG_EXIF;
+        }
+
+        output.seek(SeekFrom::Start(flags_at))?;
+        output.write_all(&[flags])?;
+    }
+
+    let riff_len = u32::try_from(end - 8).map_err(|_| malformed())?;
+    output.seek(SeekFrom::Start(4))?;
+    output.write_all(&riff_len.to_le_bytes())?;
+    output.seek(SeekFrom::Start(end))?;
+
+    Ok(())
+}
+
+fn write_webp_chunk(output: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    output.write_all(kind)?;
+    output.write_all(&(data.len() as u32).to_le_bytes())?;
+    output.write_all(data)?;
+    if data.len() & 1 == 1 {
+        output.write_all(&[0])?;
+    }
+
+    Ok(())
+}
+
+fn strip_gif(
+    input: &mut impl Read,
+    output: &mut impl Write,
+    policy: MetadataPolicy,
+) -> io::Result<()> {
+    // The signature and the logical screen descriptor
+    let mut header = [0u8; 13];
+    input.read_exact(&mut header)?;
+    if &header[..3] != b"GIF" {
+        return Err(malformed());
+    }
+    output.write_all(&header)?;
+    copy_gif_color_table(input, output, header[10])?;
+
+    loop {
+        let mut introducer = [0u8; 1];
+        input.read_exact(&mut introducer)?;
+
+        match introducer[0] {
+            GIF_IMAGE => {
+                let mut descriptor = [0u8; 9];
+                input.read_exact(&mut descriptor)?;
+                output.write_all(&introducer)?;
+                output.write_all(&descriptor)?;
+                copy_gif_color_table(input, output, descriptor[8])?;
+
+                // The LZW code size, then the image data
+                copy_exact(input, output, 1)?;
+                copy_gif_blocks(input, output)?;
+            }
+            GIF_EXTENSION => {
+                let mut label = [0u8; 1];
+                input.read_exact(&mut label)?;
+
+                // Application extensions start with a block naming what they are
+                let mut first_block = vec![];
+                if label[0] == GIF_APPLICATION {
+                    let mut len = [0u8; 1];
+                    input.read_exact(&mut len)?;
+                    first_block.resize(1 + len[0] as usize, 0);
+                    first_block[0] = len[0];
+                    input.read_exact(&mut first_block[1..])?;
+                }
+
+                let keep = match label[0] {
+                    GIF_GRAPHIC_CONTROL | GIF_PLAIN_TEXT => true,
+                    GIF_COMMENT => !matches!(policy, MetadataPolicy::LocationAndCamera),
+                    GIF_APPLICATION => GIF_KEPT_APPLICATIONS
+                        .iter()
+                        .any(|id| first_block[1..].starts_with(id)),
+                    _ => false,
+                };
+
+                // An empty first block is also the last
+                let has_more = first_block != [0];
+                if keep {
+                    output.write_all(&introducer)?;
+                    output.write_all(&label)?;
+                    output.write_all(&first_block)?;
+                    if has_more {
+                        copy_gif_blocks(input, output)?;
+                    }
+                } else if has_more {
+                    copy_gif_blocks(input, &mut io::sink())?;
+                }
+            }
+            GIF_TRAILER => return output.write_all(&introducer),
+            _ => return Err(malformed()),
+        }
+    }
+}
+
+// Copies the global or local colour table, if the flags say there is one
+fn copy_gif_color_table(
+    input: &mut impl Read,
+    output: &mut impl Write,
+    flags: u8,
+) -> io::Result<()> {
+    if flags & 0x80 == 0 {
+        return Ok(());
+    }
+
+    copy_exact(input, output, 3 << ((flags & 0x07) + 1))
+}
+
+// Copies data sub-blocks up to and including the empty one that ends them
+fn copy_gif_blocks(input: &mut impl Read, output: &mut impl Write) -> io::Result<()> {
+    loop {
+        let mut len = [0u8; 1];
+        input.read_exact(&mut len)?;
+        output.write_all(&len)?;
+
+        if len[0] == 0 {
+            return Ok(());
+        }
+        copy_exact(input, output, len[0] as u64)?;
+    }
+}
+
+// TIFF keeps its metadata in the same tags as the image structure, so the
+// tags the policy doesn't allow are taken out of each IFD and everything they
+// pointed to is zeroed. The image data stays where it is.
+fn strip_tiff(file: &mut File, policy: MetadataPolicy) -> io::Result<()> {
+    let mut tiff = Tiff::open(file)?;
+
+    let mut offset = tiff.u32_at(4)? as u64;
+    let mut visited = vec![];
+
+    while offset != 0 {
+        if visited.contains(&offset) || visited.len() >= TIFF_MAX_IFDS {
+            return Err(malformed());
+        }
+        visited.push(offset);
+
+        offset = tiff.strip_ifd(offset, policy)?;
+    }
+
+    Ok(())
+}
+
+// Tags describing where and with what a picture was taken, besides the ones
+// pointing to the EXIF and GPS IFDs
+const TIFF_CAMERA_TAGS: &[u16] = &[
+    0x010d, // DocumentName
+    0x010e, // ImageDescription
+    0x010f, // Make
+    0x0110, // Model
+    0x0131, // Software
+    0x0132, // DateTime
+    0x013c, // HostComputer
+    0x83bb, // IPTC
+    0x8649, // Photoshop
+    0xc4a5, // PrintIM
+];
+const TIFF_EXIF_IFD: u16 = 0x8769;
+const TIFF_GPS_IFD: u16 = 0x8825;
+const TIFF_XMP: u16 = 0x02bc;
+const TIFF_MAX_IFDS: usize = 64;
+
+struct Tiff<'a> {
+    file: &'a mut File,
+    little_endian: bool,
+    len: u64,
+}
+
+impl<'a> Tiff<'a> {
+    fn open(file: &'a mut File) -> io::Result<Self> {
+        let len = file.metadata()?.len();
+
+        let mut header = [0u8; 4];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut header)?;
+
+        // BigTIFF (43) has wider offsets and isn't supported
+        let little_endian = match header {
+            [b'I', b'I', 42, 0] => true,
+            [b'M', b'M', 0, 42] => false,
+            _ => return Err(malformed()),
+        };
+
+        Ok(Tiff {
+            file,
+            little_endian,
+            len,
+        })
+    }
+
+    // Takes the tags the policy doesn't allow out of the IFD at `offset`,
+    // returning where the next IFD is
+    fn strip_ifd(&mut self, offset: u64, policy: MetadataPolicy) -> io::Result<u64> {
+        let count = self.u16_at(offset)? as u64;
+        let entries = self.bytes_at(offset + 2, count * 12)?;
+        let next = self.u32_at(offset + 2 + count * 12)?;
+
+        let mut kept = vec![];
+        for entry in entries.chunks_exact(12) {
+            let tag = self.u16(&entry[0..2]);
+            let removed = match policy {
+                MetadataPolicy::Keep => false,
+                MetadataPolicy::Location => tag == TIFF_GPS_IFD || tag == TIFF_XMP,
+                MetadataPolicy::LocationAndCamera => {
+                    tag == TIFF_GPS_IFD
+                        || tag == TIFF_XMP
+                        || tag == TIFF_EXIF_IFD
+                        || TIFF_CAMERA_TAGS.contains(&tag)
+                }
+            };
+
+            if !removed {
+                kept.extend_from_slice(entry);
+            } else if tag == TIFF_GPS_IFD || tag == TIFF_EXIF_IFD {
+                let sub_ifd = self.u32(&entry[8..12]) as u64;
+                self.zero_ifd(sub_ifd)?;
+            } else {
+                self.zero_value(entry)?;
+            }
+        }
+
+        // Entries are packed to the front, the count and next offset are
+        // rewritten to match and what's left over is zeroed
+        let kept_count = kept.len() as u64 / 12;
+        let mut ifd = self.u16_bytes(kept_count as u16);
+        ifd.extend_from_slice(&kept);
+        ifd.extend_from_slice(&self.u32_bytes(next));
+        ifd.resize(2 + count as usize * 12 + 4, 0);
+        self.write_at(offset, &ifd)?;
+
+        Ok(next as u64)
+    }
+
+    // Zeroes an EXIF or GPS IFD along with all of its values. Sub-IFDs in it
+    // (interoperability) are only left unreferenced.
+    fn zero_ifd(&mut self, offset: u64) -> io::Result<()> {
+        let count = self.u16_at(offset)? as u64;
+        let entries = self.bytes_at(offset + 2, count * 12)?;
+
+        for entry in entries.chunks_exact(12) {
+            self.zero_value(entry)?;
+        }
+
+        self.write_at(offset, &vec![0u8; 2 + count as usize * 12 + 4])
+    }
+
+    // Zeroes an entry's value when it doesn't fit in the entry itself
+    fn zero_value(&mut self, entry: &[u8]) -> io::Result<()> {
+        let size = match self.u16(&entry[2..4]) {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 | 13 => 4,
+            5 | 10 | 12 => 8,
+            _ => return Err(malformed()),
+        };
+        let len = size * self.u32(&entry[4..8]) as u64;
+
+        if len > 4 {
+            let offset = self.u32(&entry[8..12]) as u64;
+            self.check_range(offset, len)?;
+            self.file.seek(SeekFrom::Start(offset))?;
+            copy_exact(&mut io::repeat(0), self.file, len)?;
+        }
+
+        Ok(())
+    }
+
+    fn bytes_at(&mut self, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        self.check_range(offset, len)?;
+        let mut bytes = vec![0u8; len as usize];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut bytes)?;
+
+        Ok(bytes)
+    }
+
+    fn write_at(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        self.check_range(offset, bytes.len() as u64)?;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(bytes)
+    }
+
+    fn check_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        if offset.checked_add(len).is_none_or(|end| end > self.len) {
+            return Err(malformed());
+        }
+
+        Ok(())
+    }
+
+    fn u16_at(&mut self, offset: u64) -> io::Result<u16> {
+        let bytes = self.bytes_at(offset, 2)?;
+        Ok(self.u16(&bytes))
+    }
+
+    fn u32_at(&mut self, offset: u64) -> io::Result<u32> {
+        let bytes = self.bytes_at(offset, 4)?;
+        Ok(self.u32(&bytes))
+    }
+
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = bytes.try_into().unwrap();
+        if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes.try_into().unwrap();
+        if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    }
+
+    fn u16_bytes(&self, n: u16) -> Vec<u8> {
+        if self.little_endian {
+            n.to_le_bytes().to_vec()
+        } else {
+            n.to_be_bytes().to_vec()
+        }
+    }
+
+    fn u32_bytes(&self, n: u32) -> Vec<u8> {
+        if self.little_endian {
+            n.to_le_bytes().to_vec()
+        } else {
+            n.to_be_bytes().to_vec()
+        }
+    }
+}
+
+// Fills `header` with the next chunk's header, false when the file ended
+// cleanly before it
+fn read_header(input: &mut impl Read, header: &mut [u8]) -> io::Result<bool> {
+    if input.read(&mut header[..1])? == 0 {
+        return Ok(false);
+    }
+    input.read_exact(&mut header[1..])?;
+
+    Ok(true)
+}
+
+fn copy_exact(input: &mut impl Read, output: &mut impl Write, len: u64) -> io::Result<()> {
+    if io::copy(&mut input.take(len), output)? == len {
+        Ok(())
+    } else {
+        Err(malformed())
+    }
+}
+
+fn skip(input: &mut impl Read, len: u64) -> io::Result<()> {
+    copy_exact(input, &mut io::sink(), len)
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "truncated or malformed container")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use exif::Rational;
+    use image::RgbImage;
+    use uuid::Uuid;
+
+    use super::*;
+
+    const FORMATS: &[ImageFormat] = &[
+        ImageFormat::Jpeg,
+        ImageFormat::Png,
+        ImageFormat::WebP,
+        ImageFormat::Tiff,
+        ImageFormat::Gif,
+    ];
+    const POLICIES: &[MetadataPolicy] = &[
+        MetadataPolicy::Keep,
+        MetadataPolicy::Location,
+        MetadataPolicy::LocationAndCamera,
+    ];
+    const XMP: &[u8] = b"<x:xmpmeta>xmpsecret</x:xmpmeta>";
+    const COMMENT: &[u8] = b"Taken with SecretCam";
+
+    // A directory of its own under the system temp directory, removed again
+    // when the test is done
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("metadata-{}", Uuid::new_v4()));
+            std::fs::create_dir(&dir).unwrap();
+
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn pixels() -> RgbImage {
+        RgbImage::from_fn(8, 8, |x, y| image::Rgb([x as u8 * 30, y as u8 * 30, 128]))
+    }
+
+    fn ascii(tag: Tag, value: &str) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![value.as_bytes().to_vec()]),
+        }
+    }
+
+    fn field(tag: Tag, value: Value) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value,
+        }
+    }
+
+    // Where and with what the picture was taken, who by and which way up
+    fn metadata_fields() -> Vec<Field> {
+        vec![
+            ascii(Tag::Make, "SecretCam"),
+            ascii(Tag::Model, "ModelXYZ"),
+            ascii(Tag::Artist, "Jinwon Kim"),
+            ascii(Tag::Copyright, "Copyright Jinwon Kim"),
+            field(Tag::Orientation, Value::Short(vec![6])),
+            ascii(Tag::DateTimeOriginal, "2022:06:01 12:00:00"),
+            ascii(Tag::GPSLatitudeRef, "N"),
+            field(
+                Tag::GPSLatitude,
+                Value::Rational(vec![
+                    Rational::from((37, 1)),
+                    Rational::from((33, 1)),
+                    Rational::from((59, 1)),
+                ]),
+            ),
+        ]
+    }
+
+    fn raw_exif() -> Vec<u8> {
+        let fields = metadata_fields();
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+
+        let mut out = Cursor::new(vec![]);
+        writer.write(&mut out, false).unwrap();
+
+        out.into_inner()
+    }
+
+    fn encoded(format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Cursor::new(vec![]);
+        DynamicImage::ImageRgb8(pixels())
+            .write_to(&mut bytes, format)
+            .unwrap();
+
+        bytes.into_inner()
+    }
+
+    // An image in each format carrying EXIF (except GIF, which has none), XMP
+    // and for GIF a comment
+    fn with_metadata(format: ImageFormat) -> Vec<u8> {
+        match format {
+            ImageFormat::Jpeg => {
+                let image = encoded(format);
+
+                let mut out = JPEG_SOI.to_vec();
+                for data in [
+                    [JPEG_EXIF_PREFIX, &raw_exif()].concat(),
+                    [JPEG_XMP_PREFIX, XMP].concat(),
+                ] {
+                    out.extend_from_slice(&[0xff, JPEG_APP1]);
+                    out.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+                    out.extend_from_slice(&data);
+                }
+                out.extend_from_slice(&image[2..]);
+
+                out
+            }
+            ImageFormat::Png => {
+                let image = encoded(format);
+                // The signature and IHDR
+                let (start, rest) = image.split_at(8 + 8 + 13 + 4);
+
+                let mut out = start.to_vec();
+                write_png_chunk(&mut out, b"eXIf", &raw_exif()).unwrap();
+                // No compression and no language
+                let itxt = [PNG_XMP_KEYWORD, &[0, 0, 0, 0], XMP].concat();
+                write_png_chunk(&mut out, b"iTXt", &itxt).unwrap();
+                out.extend_from_slice(rest);
+
+                out
+            }
+            ImageFormat::WebP => {
+                let pixels = pixels();
+                let image = webp::Encoder::from_rgb(pixels.as_raw(), 8, 8).encode_lossless();
+
+                let mut out = b"RIFF\0\0\0\0WEBP".to_vec();
+                // The flags, then the canvas size less one
+                let mut vp8x = vec![WEBP_FLAG_EXIF | WEBP_FLAG_XMP, 0, 0, 0];
+                vp8x.extend_from_slice(&[7, 0, 0, 7, 0, 0]);
+                write_webp_chunk(&mut out, b"VP8X", &vp8x).unwrap();
+                out.extend_from_slice(&image[12..]);
+                write_webp_chunk(&mut out, b"EXIF", &raw_exif()).unwrap();
+                write_webp_chunk(&mut out, b"XMP ", XMP).unwrap();
+
+                let riff_len = out.len() as u32 - 8;
+                out[4..8].copy_from_slice(&riff_len.to_le_bytes());
+
+                out
+            }
+            ImageFormat::Tiff => {
+                let pixels = pixels();
+                let strips = [pixels.as_raw().as_slice()];
+
+                let mut fields = metadata_fields();
+                fields.extend([
+                    field(Tag::ImageWidth, Value::Long(vec![8])),
+                    field(Tag::ImageLength, Value::Long(vec![8])),
+                    field(Tag::BitsPerSample, Value::Short(vec![8, 8, 8])),
+                    field(Tag::Compression, Value::Short(vec![1])),
+                    field(Tag::PhotometricInterpretation, Value::Short(vec![2])),
+                    field(Tag::SamplesPerPixel, Value::Short(vec![3])),
+                    field(Tag::RowsPerStrip, Value::Long(vec![8])),
+                    field(Tag(Context::Tiff, TIFF_XMP), Value::Byte(XMP.to_vec())),
+                ]);
+
+                let mut writer = Writer::new();
+                for field in &fields {
+                    writer.push_field(field);
+                }
+                writer.set_strips(&strips, In::PRIMARY);
+
+                let mut out = Cursor::new(vec![]);
+                writer.write(&mut out, true).unwrap();
+
+                out.into_inner()
+            }
+            ImageFormat::Gif => {
+                let image = encoded(format);
+                let flags = image[10];
+                let mut start = 13;
+                if flags & 0x80 != 0 {
+                    start += 3 << ((flags & 0x07) + 1);
+                }
+
+                let mut out = image[..start].to_vec();
+                // Loop forever
+                out.extend_from_slice(&[GIF_EXTENSION, GIF_APPLICATION, 11]);
+                out.extend_from_slice(b"NETSCAPE2.0\x03\x01\0\0\0");
+                out.extend_from_slice(&[GIF_EXTENSION, GIF_APPLICATION, 11]);
+                out.extend_from_slice(b"XMP DataXMP");
+                out.push(XMP.len() as u8);
+                out.extend_from_slice(XMP);
+                out.push(0);
+                out.extend_from_slice(&[GIF_EXTENSION, GIF_COMMENT, COMMENT.len() as u8]);
+                out.extend_from_slice(COMMENT);
+                out.push(0);
+                out.extend_from_slice(&image[start..]);
+
+                out
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn strip_bytes(
+        bytes: &[u8],
+        format: ImageFormat,
+        policy: MetadataPolicy,
+    ) -> Result<Vec<u8>, Error> {
+        let dir = TempDir::new();
+        let from = dir.0.join("upload");
+        let to = dir.0.join("original");
+        std::fs::write(&from, bytes).unwrap();
+
+        let result = strip(&from, &to, format, policy);
+        if result.is_err() {
+            assert!(!to.exists(), "Left a file behind after failing");
+        }
+
+        result.map(|_| std::fs::read(&to).unwrap())
+    }
+
+    fn contains(bytes: &[u8], needle: &[u8]) -> bool {
+        bytes.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn fixtures_carry_metadata() {
+        for &format in FORMATS {
+            let bytes = with_metadata(format);
+
+            assert!(contains(&bytes, b"xmpsecret"), "{:?}", format);
+            assert_eq!(
+                image::load_from_memory_with_format(&bytes, format)
+                    .unwrap()
+                    .into_rgb8()
+                    .dimensions(),
+                (8, 8),
+                "{:?}",
+                format
+            );
+
+            if format != ImageFormat::Gif {
+                let exif = Reader::new()
+                    .read_from_container(&mut Cursor::new(&bytes))
+                    .unwrap();
+                for tag in [Tag::Make, Tag::GPSLatitude, Tag::Artist] {
+                    assert!(exif.get_field(tag, In::PRIMARY).is_some(), "{:?}", format);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_uploads_as_they_are() {
+        for &format in FORMATS {
+            let bytes = with_metadata(format);
+
+            let stripped = strip_bytes(&bytes, format, MetadataPolicy::Keep).unwrap();
+            assert_eq!(stripped, bytes, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn removes_what_the_policy_asks_for() {
+        for &format in FORMATS {
+            for &policy in &POLICIES[1..] {
+                let context = format!("{:?} {:?}", format, policy);
+                let stripped = strip_bytes(&with_metadata(format), format, policy).unwrap();
+
+                let image = image::load_from_memory_with_format(&stripped, format).unwrap();
+                assert_eq!(image.into_rgb8().dimensions(), (8, 8), "{}", context);
+                assert!(!contains(&stripped, b"xmpsecret"), "{}", context);
+
+                let removes_camera = matches!(policy, MetadataPolicy::LocationAndCamera);
+                if format == ImageFormat::Gif {
+                    assert!(contains(&stripped, b"NETSCAPE2.0"), "{}", context);
+                    assert_eq!(contains(&stripped, COMMENT), !removes_camera, "{}", context);
+                    continue;
+                }
+
+                let exif = Reader::new()
+                    .read_from_container(&mut Cursor::new(&stripped))
+                    .unwrap();
+                let has = |tag| exif.get_field(tag, In::PRIMARY).is_some();
+
+                assert!(!has(Tag::GPSLatitude), "{}", context);
+                assert!(!has(Tag::GPSLatitudeRef), "{}", context);
+                assert!(
+                    exif.fields().all(|f| f.tag.context() != Context::Gps),
+                    "{}",
+                    context
+                );
+                assert_eq!(has(Tag::Make), !removes_camera, "{}", context);
+                assert_eq!(has(Tag::Model), !removes_camera, "{}", context);
+                assert_eq!(has(Tag::DateTimeOriginal), !removes_camera, "{}", context);
+                assert_eq!(
+                    contains(&stripped, b"SecretCam"),
+                    !removes_camera,
+                    "{}",
+                    context
+                );
+
+                assert!(has(Tag::Artist), "{}", context);
+                assert!(has(Tag::Copyright), "{}", context);
+                assert_eq!(
+                    exif.get_field(Tag::Orientation, In::PRIMARY)
+                        .and_then(|f| f.value.get_uint(0)),
+                    Some(6),
+                    "{}",
+                    context
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_uploads() {
+        for &format in FORMATS {
+            let bytes = &with_metadata(format)[..20];
+
+            for &policy in &POLICIES[1..] {
+                assert!(
+                    matches!(
+                        strip_bytes(bytes, format, policy),
+                        Err(Error::MalformedImage)
+                    ),
+                    "{:?} {:?}",
+                    format,
+                    policy
+                );
+            }
+
+            // Nothing needs taking apart to store it as is
+            let kept = strip_bytes(bytes, format, MetadataPolicy::Keep).unwrap();
+            assert_eq!(kept, bytes, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_uploads() {
+        let mut tiff_loop = with_metadata(ImageFormat::Tiff);
+        // Point the first IFD's next IFD back at itself
+        let ifd = u32::from_le_bytes(tiff_loop[4..8].try_into().unwrap()) as usize;
+        let count = u16::from_le_bytes(tiff_loop[ifd..ifd + 2].try_into().unwrap()) as usize;
+        let next = ifd + 2 + count * 12;
+        tiff_loop[next..next + 4].copy_from_slice(&(ifd as u32).to_le_bytes());
+
+        let mut png_chunk = with_metadata(ImageFormat::Png);
+        // A chunk longer than the rest of the file
+        png_chunk[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+
+        let mut gif_block = with_metadata(ImageFormat::Gif);
+        let comment = gif_block
+            .windows(2)
+            .position(|w| w == [GIF_EXTENSION, GIF_COMMENT])
+            .unwrap();
+        // Neither an image, an extension nor the end
+        gif_block[comment] = 0x00;
+
+        let cases: Vec<(ImageFormat, Vec<u8>)> = vec![
+            (ImageFormat::Jpeg, vec![0xff, 0xd8, 0x00, 0xe0, 0x00, 0x10]),
+            (ImageFormat::Jpeg, vec![0xff, 0xd8, 0xff, 0xe0, 0x00, 0x01]),
+            (ImageFormat::Png, b"\x89PNG\r\n\x1a\x0b".to_vec()),
+            (ImageFormat::Png, png_chunk),
+            (ImageFormat::WebP, b"RIFF\0\0\0\0WEBX".to_vec()),
+            (ImageFormat::Tiff, b"II*\0\xff\xff\0\0".to_vec()),
+            (
+                ImageFormat::Tiff,
+                b"II+\0\x10\0\0\0\0\0\0\0\0\0\0\0".to_vec(),
+            ),
+            (ImageFormat::Tiff, tiff_loop),
+            (ImageFormat::Gif, b"GIX89a".to_vec()),
+            (ImageFormat::Gif, gif_block),
+        ];
+
+        for (i, (format, bytes)) in cases.iter().enumerate() {
+            for &policy in &POLICIES[1..] {
+                assert!(
+                    matches!(
+                        strip_bytes(bytes, *format, policy),
+                        Err(Error::MalformedImage)
+                    ),
+                    "case {} {:?} {:?}",
+                    i,
+                    format,
+                    policy
+                );
+            }
+        }
+    }
+}
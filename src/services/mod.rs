@@ -1,5 +1,20 @@
 pub mod auth;
+pub mod cleanup;
+pub mod compression;
 pub mod database;
+pub mod derivatives;
+pub mod display;
+pub mod formats;
+pub mod http_cache;
+pub mod ip_allowlist;
+pub mod jobs;
+pub mod metadata;
 pub mod password;
+pub mod placeholder;
+pub mod security_headers;
+pub mod smartcrop;
 pub mod static_files;
 pub mod thumbs;
+pub mod uploads;
+pub mod watermark;
+pub mod webauthn;
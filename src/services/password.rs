@@ -1,4 +1,7 @@
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use argon2::{
+    password_hash::{rand_core::OsRng, SaltString},
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+};
 
 pub fn verify_password(password: &str, phc: &str) -> anyhow::Result<bool> {
     let parsed_hash = PasswordHash::new(phc)?;
@@ -7,3 +10,34 @@ pub fn verify_password(password: &str, phc: &str) -> anyhow::Result<bool> {
         .verify_password(password.trim().as_bytes(), &parsed_hash)
         .is_ok())
 }
+
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    let hash = Argon2::default()
+        .hash_password(password.trim().as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("Failed to hash password: {}", e))?;
+
+    Ok(hash.to_string())
+}
+
+// True when a stored hash was made with a different algorithm, version or
+// cost parameters than the ones we'd use to hash a new password today.
+pub fn needs_rehash(phc: &str) -> anyhow::Result<bool> {
+    let parsed_hash = PasswordHash::new(phc)?;
+
+    let algorithm = Algorithm::try_from(parsed_hash.algorithm)?;
+    let version = parsed_hash
+        .version
+        .map(Version::try_from)
+        .transpose()?
+        .unwrap_or_default();
+    let params = Params::try_from(&parsed_hash)?;
+    let current = Params::default();
+
+    Ok(algorithm != Algorithm::default()
+        || version != Version::default()
+        || params.m_cost() != current.m_cost()
+        || params.t_cost() != current.t_cost()
+        || params.p_cost() != current.p_cost())
+}
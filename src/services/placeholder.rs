@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+
+use image::{DynamicImage, ImageFormat};
+
+use super::formats;
+
+// The thumbnail is scaled down to this before counting colours, which is
+// plenty to tell which one covers the most of it
+const COLOR_SAMPLE_SIZE: u32 = 32;
+// Longest edge of the preview, which the browser blurs as it scales it up
+const PREVIEW_SIZE: u32 = 16;
+
+/// What's shown in place of a thumbnail while it loads
+pub struct Placeholder {
+    pub width: u32,
+    pub height: u32,
+    /// As #rrggbb
+    pub dominant_color: String,
+    /// A tiny JPEG copy as a data URI, small enough to be in the page itself
+    pub preview: String,
+}
+
+impl Placeholder {
+    pub fn new(thumb: &DynamicImage) -> anyhow::Result<Self> {
+        let preview = formats::encode(
+            &thumb.thumbnail(PREVIEW_SIZE, PREVIEW_SIZE),
+            ImageFormat::Jpeg,
+        )?;
+
+        Ok(Placeholder {
+            width: thumb.width(),
+            height: thumb.height(),
+            dominant_color: dominant_color(thumb),
+            preview: format!("data:image/jpeg;base64,{}", base64::encode(preview)),
+        })
+    }
+}
+
+// Colours are grouped by their top four bits in each channel, and the most
+// common group's average wins. Averaging the whole image instead turns most
+// paintings a muddy brown.
+fn dominant_color(thumb: &DynamicImage) -> String {
+    let sample = thumb
+        .thumbnail(COLOR_SAMPLE_SIZE, COLOR_SAMPLE_SIZE)
+        .to_rgb8();
+
+    let mut groups: HashMap<[u8; 3], (u64, [u64; 3])> = HashMap::new();
+    for pixel in sample.pixels() {
+        let [r, g, b] = pixel.0;
+        let (count, sums) = groups.entry([r >> 4, g >> 4, b >> 4]).or_default();
+
+        *count += 1;
+        sums[0] += r as u64;
+        sums[1] += g as u64;
+        sums[2] += b as u64;
+    }
+
+    match groups.values().max_by_key(|(count, _)| *count) {
+        Some((count, [r, g, b])) => {
+            format!("#{:02x}{:02x}{:02x}", r / count, g / count, b / count)
+        }
+        None => "#000000".to_owned(),
+    }
+}
@@ -0,0 +1,113 @@
+use axum::http::{
+    header::{
+        HeaderName, CONTENT_SECURITY_POLICY, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY,
+        X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+    },
+    HeaderValue, Request,
+};
+use axum::{middleware::Next, response::Response};
+
+use crate::model::error::Error;
+
+// The public templates use inline style attributes, and the image showcase sets
+// its background through one, so inline styles have to be allowed.
+pub const DEFAULT_PUBLIC_CSP: &str = "default-src 'self'; img-src 'self' data:; \
+    style-src 'self' 'unsafe-inline'; script-src 'self'; object-src 'none'; \
+    base-uri 'self'; form-action 'self'; frame-ancestors 'none'";
+
+// Cropper previews uploads from blob: URLs and reads them back with XHR to
+// check their orientation, and positions its elements with inline styles.
+pub const DEFAULT_ADMIN_CSP: &str = "default-src 'self'; img-src 'self' data: blob:; \
+    style-src 'self' 'unsafe-inline'; script-src 'self'; connect-src 'self' blob:; \
+    object-src 'none'; base-uri 'self'; form-action 'self'; frame-ancestors 'none'";
+
+const PERMISSIONS_POLICY: HeaderName = HeaderName::from_static("permissions-policy");
+
+/// Headers added to every response from the router it is layered on, unless
+/// the handler has already set them.
+#[derive(Clone)]
+pub struct SecurityHeaders {
+    headers: Vec<(HeaderName, HeaderValue)>,
+    hsts: Option<HeaderValue>,
+}
+
+impl SecurityHeaders {
+    pub fn public(csp: &str, hsts_max_age: u64) -> Result<Self, Error> {
+        Self::new(
+            csp,
+            "strict-origin-when-cross-origin",
+            "camera=(), microphone=(), geolocation=(), payment=(), usb=(), \
+            publickey-credentials-get=(), interest-cohort=()",
+            hsts_max_age,
+        )
+    }
+
+    // Passkeys need the WebAuthn APIs, which default to same origin only
+    pub fn admin(csp: &str, hsts_max_age: u64) -> Result<Self, Error> {
+        Self::new(
+            csp,
+            "same-origin",
+            "camera=(), microphone=(), geolocation=(), payment=(), usb=(), \
+            interest-cohort=()",
+            hsts_max_age,
+        )
+    }
+
+    fn new(
+        csp: &str,
+        referrer_policy: &str,
+        permissions_policy: &str,
+        hsts_max_age: u64,
+    ) -> Result<Self, Error> {
+        let value = |v: &str| {
+            HeaderValue::from_str(v)
+                .map_err(|_| Error::IllegalStateError("Invalid security header value"))
+        };
+
+        let headers = vec![
+            (CONTENT_SECURITY_POLICY, value(csp)?),
+            (X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+            (X_FRAME_OPTIONS, HeaderValue::from_static("DENY")),
+            (REFERRER_POLICY, value(referrer_policy)?),
+            (PERMISSIONS_POLICY, value(permissions_policy)?),
+        ];
+
+        let hsts = if hsts_max_age > 0 {
+            Some(value(&format!("max-age={}", hsts_max_age))?)
+        } else {
+            None
+        };
+
+        Ok(SecurityHeaders { headers, hsts })
+    }
+
+    pub async fn apply<B>(self, req: Request<B>, next: Next<B>) -> Response {
+        let https = is_https(&req);
+
+        let mut res = next.run(req).await;
+        let headers = res.headers_mut();
+
+        for (name, value) in self.headers {
+            headers.entry(name).or_insert(value);
+        }
+
+        if let (true, Some(hsts)) = (https, self.hsts) {
+            headers.entry(STRICT_TRANSPORT_SECURITY).or_insert(hsts);
+        }
+
+        res
+    }
+}
+
+// TLS is terminated by the reverse proxy in front of us, which tells us about
+// it with X-Forwarded-Proto. Browsers ignore HSTS over plain HTTP so a client
+// spoofing the header gains nothing.
+fn is_https<B>(req: &Request<B>) -> bool {
+    req.uri().scheme_str() == Some("https")
+        || req
+            .headers()
+            .get("x-forwarded-proto")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.eq_ignore_ascii_case("https"))
+            .unwrap_or(false)
+}
@@ -0,0 +1,116 @@
+use image::{DynamicImage, GrayImage};
+
+use crate::model::forms::image::Rectangle;
+
+// Images are scaled down to this before looking for detail, which is plenty
+// to find where the subject is and keeps large scans quick
+const ANALYSIS_SIZE: u32 = 256;
+// How much less a crop at the very edge counts than one in the middle, so
+// evenly detailed images are cropped from the center
+const CENTER_BIAS: f64 = 0.2;
+
+/// Suggests where to crop an image to the given width to height shape. The
+/// crop is as large as the shape allows, positioned over the most edges,
+/// which is where the detail of a piece tends to be rather than its
+/// background or margins.
+pub fn suggest_crop(image: &DynamicImage, (aspect_width, aspect_height): (u32, u32)) -> Rectangle {
+    let (width, height) = (image.width(), image.height());
+
+    let is_wider = width as u64 * aspect_height as u64 >= height as u64 * aspect_width as u64;
+    let (crop_width, crop_height) = if is_wider {
+        (
+            (height as u64 * aspect_width as u64 / aspect_height as u64) as u32,
+            height,
+        )
+    } else {
+        (
+            width,
+            (width as u64 * aspect_height as u64 / aspect_width as u64) as u32,
+        )
+    };
+
+    // Already the right shape, or too small to tell
+    let slack = if is_wider {
+        width - crop_width
+    } else {
+        height - crop_height
+    };
+    if slack == 0 {
+        return rectangle(0, 0, crop_width, crop_height);
+    }
+
+    let small = image.thumbnail(ANALYSIS_SIZE, ANALYSIS_SIZE).to_luma8();
+    let edges = edge_density(&small, is_wider);
+
+    // The crop spans the whole of the other side, so it only moves one way
+    let scale = if is_wider {
+        width as f64 / small.width() as f64
+    } else {
+        height as f64 / small.height() as f64
+    };
+    let window =
+        ((if is_wider { crop_width } else { crop_height }) as f64 / scale).round() as usize;
+    let window = window.clamp(1, edges.len());
+
+    let offset = best_window(&edges, window);
+
+    let offset = ((offset as f64 * scale).round() as u32).min(slack);
+    if is_wider {
+        rectangle(offset, 0, crop_width, crop_height)
+    } else {
+        rectangle(0, offset, crop_width, crop_height)
+    }
+}
+
+// How much each column, or each row, of the image changes from pixel to pixel
+fn edge_density(image: &GrayImage, by_column: bool) -> Vec<f64> {
+    let (width, height) = image.dimensions();
+    let mut density = vec![0.0; if by_column { width } else { height } as usize];
+
+    let at = |x: u32, y: u32| image.get_pixel(x, y)[0] as f64;
+    for y in 1..height.saturating_sub(1) {
+        for x in 1..width.saturating_sub(1) {
+            let gradient =
+                (at(x + 1, y) - at(x - 1, y)).abs() + (at(x, y + 1) - at(x, y - 1)).abs();
+
+            density[if by_column { x } else { y } as usize] += gradient;
+        }
+    }
+
+    density
+}
+
+// Where the run of `window` values adds up to the most, leaning to the middle
+fn best_window(values: &[f64], window: usize) -> usize {
+    let last = values.len() - window;
+    let center = last as f64 / 2.0;
+
+    let mut sum: f64 = values[..window].iter().sum();
+    let mut best = (0, f64::MIN);
+    for offset in 0..=last {
+        if offset > 0 {
+            sum += values[offset + window - 1] - values[offset - 1];
+        }
+
+        let off_center = if center > 0.0 {
+            (offset as f64 - center).abs() / center
+        } else {
+            0.0
+        };
+        let score = sum * (1.0 - CENTER_BIAS * off_center);
+        if score > best.1 {
+            best = (offset, score);
+        }
+    }
+
+    best.0
+}
+
+fn rectangle(x: u32, y: u32, width: u32, height: u32) -> Rectangle {
+    Rectangle {
+        x: x as f64,
+        y: y as f64,
+        width: width as f64,
+        height: height as f64,
+    }
+}
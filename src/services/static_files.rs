@@ -1,116 +1,1115 @@
-use std::path::{Path, PathBuf};
+use std::{
+    io::{ErrorKind, SeekFrom},
+    ops::Bound,
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use axum::body::Bytes;
-use image::{ImageBuffer, Rgba};
-use tokio::{fs::File, io::AsyncWriteExt};
+use axum::{
+    body::StreamBody,
+    headers::{ContentLength, ContentRange, ETag, HeaderMapExt, IfRange, LastModified, Range},
+    http::{
+        header::{ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_TYPE, VARY},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use image::{DynamicImage, ImageFormat};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
 
-use crate::model::error::Error;
+use crate::{
+    model::error::Error,
+    services::{
+        compression::{self, Encoding},
+        derivatives::{self, Derivative},
+        formats,
+        http_cache::{self, IMMUTABLE, REVALIDATE},
+        thumbs::DEFAULT_PROFILE,
+    },
+};
+
+// The empty extension is for uploads from before we checked what was uploaded,
+// which were saved without one when the browser's filename wasn't recognised.
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "webp", "gif", "tif", "tiff", "bmp", "",
+];
+const STYLE_EXTENSIONS: &[&str] = &["css"];
+const JS_EXTENSIONS: &[&str] = &["js"];
+const DERIVATIVE_EXTENSIONS: &[&str] = &["avif", "webp"];
+
+// Enough of the start of a file for `image::guess_format` to recognise it
+const MAGIC_BYTES_LEN: usize = 16;
 
 #[derive(Clone)]
 pub struct StaticFiles {
     image_root: PathBuf,
     thumbs_root: PathBuf,
+    display_root: PathBuf,
     styles_root: PathBuf,
     js_root: PathBuf,
+    staging_root: PathBuf,
+}
+
+/// Somewhere to make an upload's files before anything refers to them. The
+/// staged files are laid out like the real ones, so the usual functions can
+/// write them, and they're moved into place together once the upload is
+/// known to be good.
+pub struct Staging {
+    dir: PathBuf,
+    files: StaticFiles,
+    // Moved into place, in case the upload has to be undone after all
+    published: Vec<PathBuf>,
+}
+
+/// A file that has been found on disk but not read yet, so that requests
+/// which can be answered with a 304 never touch the contents.
+pub struct StaticFile {
+    path: PathBuf,
+    pub content_type: &'static str,
+    pub cache_control: &'static str,
+    pub content_encoding: Option<Encoding>,
+    // The request header used to pick between versions of the file, if any
+    pub vary: Option<&'static str>,
+    pub len: u64,
+    pub modified: SystemTime,
 }
 
 impl StaticFiles {
     pub fn new(root_dir: impl AsRef<Path>) -> Self {
         let image_root = root_dir.as_ref().join("images").canonicalize().unwrap();
         let thumbs_root = root_dir.as_ref().join("thumbs").canonicalize().unwrap();
+
+        // Newer than the other directories so might not exist on older installs
+        let display_root = root_dir.as_ref().join("display");
+        std::fs::create_dir_all(&display_root).unwrap();
+        let display_root = display_root.canonicalize().unwrap();
         let styles_root = root_dir.as_ref().join("styles").canonicalize().unwrap();
         let js_root = root_dir.as_ref().join("js").canonicalize().unwrap();
 
+        // Inside the root so that staged files can be renamed into place
+        let staging_root = root_dir.as_ref().join("staging");
+        std::fs::create_dir_all(&staging_root).unwrap();
+        let staging_root = staging_root.canonicalize().unwrap();
+
         tracing::info!("Using images root: {}", image_root.display());
         tracing::info!("Using thumbs root: {}", thumbs_root.display());
+        tracing::info!("Using display root: {}", display_root.display());
         tracing::info!("Using styles root: {}", styles_root.display());
+        tracing::info!("Using staging root: {}", staging_root.display());
 
         StaticFiles {
             image_root,
             thumbs_root,
+            display_root,
             styles_root,
             js_root,
+            staging_root,
         }
     }
 
-    pub async fn save_image(
-        &self,
-        file_path: impl AsRef<Path>,
-        bytes: &Bytes,
-    ) -> Result<(), Error> {
-        let mut path = self.image_root.clone();
-        path.push(file_path);
+    pub async fn stage(&self) -> Result<Staging, Error> {
+        let dir = self.staging_root.join(Uuid::new_v4().to_string());
+        for subdir in ["images", "thumbs", "display"] {
+            tokio::fs::create_dir_all(dir.join(subdir)).await?;
+        }
+        let dir = dir.canonicalize()?;
 
-        let canonical = path.parent().unwrap().canonicalize()?;
+        tracing::debug!("Staging upload in {}", dir.display());
 
-        if canonical.starts_with(&self.image_root) {
-            tracing::info!("Saving image: {}", path.display());
+        Ok(Staging {
+            files: StaticFiles {
+                image_root: dir.join("images"),
+                thumbs_root: dir.join("thumbs"),
+                display_root: dir.join("display"),
+                styles_root: self.styles_root.clone(),
+                js_root: self.js_root.clone(),
+                staging_root: self.staging_root.clone(),
+            },
+            dir,
+            published: vec![],
+        })
+    }
 
-            let mut file = File::create(path).await?;
-            file.write_all(bytes).await?;
+    /// Removes uploads left half done by a crash or restart. Only safe before
+    /// the server starts taking requests.
+    pub async fn clear_staging(&self) -> Result<(), Error> {
+        let mut entries = tokio::fs::read_dir(&self.staging_root).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            tracing::info!("Removing stale upload {}", entry.path().display());
 
-            Ok(())
-        } else {
-            Err(Error::InvalidPath)?
+            if entry.file_type().await?.is_dir() {
+                tokio::fs::remove_dir_all(entry.path()).await?;
+            } else {
+                tokio::fs::remove_file(entry.path()).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the compressed copies of stylesheets and scripts, which are
+    /// made again as they're requested. An original replaced while the server
+    /// was down can keep the same mtime and size, so copies from before a
+    /// restart can't be trusted.
+    pub async fn clear_precompressed(&self) -> Result<(), Error> {
+        for root in [&self.styles_root, &self.js_root] {
+            let mut entries = tokio::fs::read_dir(root).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_file() && compression::is_compressed_copy(&path) {
+                    tracing::info!("Removing compressed copy {}", path.display());
+                    tokio::fs::remove_file(path).await?;
+                }
+            }
         }
+
+        Ok(())
+    }
+
+    /// Where a new original with this name is to be written
+    pub fn new_image_path(&self, file_path: impl AsRef<Path>) -> Result<PathBuf, Error> {
+        let path = resolve_new(&self.image_root, file_path.as_ref(), IMAGE_EXTENSIONS)?;
+
+        tracing::info!("Saving image: {}", path.display());
+
+        Ok(path)
     }
 
-    pub fn get_image_path(&self, name: &str) -> PathBuf {
-        self.image_root.join(name)
+    pub fn get_image_path(&self, name: &str) -> Result<PathBuf, Error> {
+        resolve(&self.image_root, name, IMAGE_EXTENSIONS)
     }
 
-    pub async fn get_image(&self, name: &str) -> Result<Vec<u8>, Error> {
-        let path = self.image_root.join(name);
+    pub async fn get_image(&self, name: &str) -> Result<StaticFile, Error> {
+        let path = resolve(&self.image_root, name, IMAGE_EXTENSIONS)?;
 
         tracing::info!("Loading image: {}", path.display());
 
-        Ok(tokio::fs::read(&path).await?)
+        let content_type = sniff_image_content_type(&path).await?;
+
+        // Uploads are saved under a new UUID rather than being overwritten, so
+        // anything named like one will never change.
+        let is_uuid = Path::new(name)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .map(|s| Uuid::parse_str(s).is_ok())
+            .unwrap_or(false);
+        let cache_control = if is_uuid { IMMUTABLE } else { REVALIDATE };
+
+        StaticFile::open(path, content_type, cache_control).await
+    }
+
+    pub async fn delete_image(&self, name: &str) -> Result<(), Error> {
+        let path = resolve(&self.image_root, name, IMAGE_EXTENSIONS)?;
+
+        tracing::info!("Deleting image: {}", path.display());
+
+        tokio::fs::remove_file(path).await?;
+
+        Ok(())
     }
 
     pub fn save_thumb(
         &self,
+        profile: &str,
         file_path: impl AsRef<Path>,
-        image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+        image: &DynamicImage,
+        format: ImageFormat,
     ) -> Result<(), Error> {
-        let file_path = file_path.as_ref();
+        let root = self.thumbs_root_for(profile)?;
+        std::fs::create_dir_all(&root)?;
+        let path = resolve_new(&root, file_path.as_ref(), IMAGE_EXTENSIONS)?;
 
-        let mut path = self.thumbs_root.clone();
-        path.push(file_path);
+        tracing::info!("Saving thumbnail: {}", path.display());
 
-        let canonical = path.parent().unwrap().canonicalize()?;
+        replace_file(&path, |tmp| Ok(formats::save(image, tmp, format)?))?;
 
-        if canonical.starts_with(&self.thumbs_root) {
-            tracing::info!("Saving thumbnail: {}", path.display());
+        Ok(())
+    }
 
-            image.save(&path)?;
+    pub fn get_thumb_path(&self, profile: &str, name: &str) -> Result<PathBuf, Error> {
+        resolve(&self.thumbs_root_for(profile)?, name, IMAGE_EXTENSIONS)
+    }
 
-            Ok(())
-        } else {
-            Err(Error::InvalidPath)?
+    pub fn save_thumb_derivative(
+        &self,
+        profile: &str,
+        name: &str,
+        derivative: Derivative,
+        bytes: &[u8],
+    ) -> Result<(), Error> {
+        save_derivative(&self.thumbs_root_for(profile)?, name, derivative, bytes)
+    }
+
+    /// Deletes the image's thumbnail in every profile, including ones that are
+    /// no longer configured
+    pub async fn delete_thumb(&self, name: &str) -> Result<(), Error> {
+        tracing::info!("Deleting thumbnail: {}", name);
+
+        delete_with_derivatives(&self.thumbs_root, name).await?;
+
+        let mut entries = tokio::fs::read_dir(&self.thumbs_root).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_dir() {
+                delete_with_derivatives(&entry.path(), name).await?;
+            }
         }
+
+        Ok(())
     }
 
-    pub async fn get_thumb(&self, name: &str) -> Result<Vec<u8>, Error> {
-        let path = self.thumbs_root.join(name);
+    /// Versioned thumbnails are asked for with the hash of the current file in
+    /// the URL, which changes whenever the file does, so they're never checked
+    /// again.
+    pub async fn get_thumb(
+        &self,
+        profile: &str,
+        name: &str,
+        versioned: bool,
+        request_headers: &HeaderMap,
+    ) -> Result<StaticFile, Error> {
+        let root = self.thumbs_root_for(profile)?;
+        let path = resolve(&root, name, IMAGE_EXTENSIONS)?;
 
         tracing::info!("Loading thumb: {}", path.display());
 
-        Ok(tokio::fs::read(&path).await?)
+        let cache_control = if versioned { IMMUTABLE } else { REVALIDATE };
+        open_negotiated_image(&root, name, path, cache_control, request_headers).await
+    }
+
+    // The default profile's thumbnails are kept where they always were, the
+    // others each have a directory inside it
+    fn thumbs_root_for(&self, profile: &str) -> Result<PathBuf, Error> {
+        if profile == DEFAULT_PROFILE {
+            return Ok(self.thumbs_root.clone());
+        }
+
+        let is_plain_name = !profile.is_empty()
+            && profile
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if is_plain_name {
+            Ok(self.thumbs_root.join(profile))
+        } else {
+            Err(Error::InvalidPath)
+        }
+    }
+
+    pub fn save_display(
+        &self,
+        name: &str,
+        image: &DynamicImage,
+        format: ImageFormat,
+    ) -> Result<(), Error> {
+        let path = resolve_new(&self.display_root, Path::new(name), IMAGE_EXTENSIONS)?;
+
+        tracing::info!("Saving display image: {}", path.display());
+
+        replace_file(&path, |tmp| Ok(formats::save(image, tmp, format)?))?;
+
+        Ok(())
+    }
+
+    pub fn save_display_derivative(
+        &self,
+        name: &str,
+        derivative: Derivative,
+        bytes: &[u8],
+    ) -> Result<(), Error> {
+        save_derivative(&self.display_root, name, derivative, bytes)
+    }
+
+    pub async fn get_display(
+        &self,
+        name: &str,
+        request_headers: &HeaderMap,
+    ) -> Result<StaticFile, Error> {
+        let path = resolve(&self.display_root, name, IMAGE_EXTENSIONS)?;
+
+        tracing::info!("Loading display image: {}", path.display());
+
+        open_negotiated_image(&self.display_root, name, path, REVALIDATE, request_headers).await
     }
 
-    pub async fn get_style(&self, name: &str) -> Result<Vec<u8>, Error> {
-        let path = self.styles_root.join(name);
+    pub async fn delete_display(&self, name: &str) -> Result<(), Error> {
+        tracing::info!("Deleting display image: {}", name);
+
+        delete_with_derivatives(&self.display_root, name).await
+    }
+
+    pub async fn get_style(
+        &self,
+        name: &str,
+        request_headers: &HeaderMap,
+    ) -> Result<StaticFile, Error> {
+        let path = resolve(&self.styles_root, name, STYLE_EXTENSIONS)?;
 
         tracing::info!("Loading style: {}", path.display());
 
-        Ok(tokio::fs::read(&path).await?)
+        StaticFile::open(path, "text/css; charset=utf-8", REVALIDATE)
+            .await?
+            .precompressed(request_headers)
+            .await
     }
 
-    pub async fn get_js(&self, name: &str) -> Result<Vec<u8>, Error> {
-        let path = self.js_root.join(name);
+    pub async fn get_js(
+        &self,
+        name: &str,
+        request_headers: &HeaderMap,
+    ) -> Result<StaticFile, Error> {
+        let path = resolve(&self.js_root, name, JS_EXTENSIONS)?;
 
         tracing::info!("Loading javascript: {}", path.display());
 
-        Ok(tokio::fs::read(&path).await?)
+        StaticFile::open(path, "text/javascript; charset=utf-8", REVALIDATE)
+            .await?
+            .precompressed(request_headers)
+            .await
+    }
+}
+
+impl Staging {
+    /// Where the upload's files are written
+    pub fn files(&self) -> &StaticFiles {
+        &self.files
+    }
+
+    /// Where the file as it was uploaded is streamed to, before it's checked.
+    /// It's never published.
+    pub fn upload_path(&self) -> PathBuf {
+        self.dir.join("upload")
+    }
+
+    /// Moves every staged file into place. Anything moved before a failure is
+    /// remembered, so `unpublish` still undoes all of it.
+    pub async fn publish(&mut self, into: &StaticFiles) -> Result<(), Error> {
+        let roots = [
+            (self.files.image_root.clone(), &into.image_root),
+            (self.files.thumbs_root.clone(), &into.thumbs_root),
+            (self.files.display_root.clone(), &into.display_root),
+        ];
+
+        for (from, to) in roots {
+            let mut entries = tokio::fs::read_dir(&from).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = to.join(entry.file_name());
+
+                // Names are fresh UUIDs, so this would be a bug rather than bad luck
+                if tokio::fs::symlink_metadata(&path).await.is_ok() {
+                    tracing::error!("Refusing to overwrite {}", path.display());
+                    return Err(Error::InvalidPath);
+                }
+
+                tracing::debug!("Publishing {}", path.display());
+                tokio::fs::rename(entry.path(), &path).await?;
+                self.published.push(path);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the files `publish` moved into place, for when what refers to
+    /// them couldn't be saved after all
+    pub async fn unpublish(&mut self) {
+        for path in self.published.drain(..) {
+            tracing::info!("Removing unused upload file {}", path.display());
+
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                tracing::warn!("Failed to remove {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Removes whatever is still staged. Left for `clear_staging` at the next
+    /// startup if that fails.
+    pub async fn discard(self) {
+        if let Err(e) = tokio::fs::remove_dir_all(&self.dir).await {
+            tracing::warn!("Failed to remove {}: {}", self.dir.display(), e);
+        }
+    }
+}
+
+impl StaticFile {
+    async fn open(
+        path: PathBuf,
+        content_type: &'static str,
+        cache_control: &'static str,
+    ) -> Result<StaticFile, Error> {
+        let metadata = tokio::fs::metadata(&path).await?;
+
+        Ok(StaticFile {
+            path,
+            content_type,
+            cache_control,
+            content_encoding: None,
+            vary: None,
+            len: metadata.len(),
+            modified: metadata.modified()?,
+        })
+    }
+
+    // Switches to a compressed copy of the file when the client accepts one.
+    // Failing to make the copy isn't fatal, the original can still be sent.
+    async fn precompressed(self, request_headers: &HeaderMap) -> Result<StaticFile, Error> {
+        let encoding = match compression::preferred_encoding(request_headers) {
+            Some(encoding) => encoding,
+            None => {
+                return Ok(StaticFile {
+                    vary: Some("Accept-Encoding"),
+                    ..self
+                })
+            }
+        };
+
+        match compression::precompressed(&self.path, encoding).await {
+            Ok(path) => {
+                let metadata = tokio::fs::metadata(&path).await?;
+
+                Ok(StaticFile {
+                    path,
+                    content_encoding: Some(encoding),
+                    vary: Some("Accept-Encoding"),
+                    len: metadata.len(),
+                    modified: metadata.modified()?,
+                    ..self
+                })
+            }
+            Err(e) => {
+                tracing::warn!("Failed to compress {}: {}", self.path.display(), e);
+
+                Ok(StaticFile {
+                    vary: Some("Accept-Encoding"),
+                    ..self
+                })
+            }
+        }
+    }
+
+    /// Strong validator made from the size and modification time, which
+    /// change whenever the file is written.
+    pub fn etag(&self) -> ETag {
+        let mtime = self
+            .modified
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+
+        format!("\"{:x}-{:x}\"", self.len, mtime).parse().unwrap()
+    }
+
+    /// Answers a request for the file, with a 304 if the client's copy is
+    /// still current. The body is streamed from disk and a single byte range
+    /// can be asked for, so large images don't have to sit in memory.
+    pub async fn into_response(self, request_headers: &HeaderMap) -> Result<Response, Error> {
+        let etag = self.etag();
+        let is_not_modified =
+            http_cache::is_not_modified(request_headers, &etag, Some(self.modified));
+        let range = requested_range(request_headers, &etag, self.modified, self.len);
+
+        let mut headers =
+            http_cache::validator_headers(etag, Some(self.modified), self.cache_control);
+        headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        if let Some(vary) = self.vary {
+            headers.insert(VARY, HeaderValue::from_static(vary));
+        }
+
+        if is_not_modified {
+            return Ok(http_cache::not_modified(headers));
+        }
+
+        let (status, start, end) = match range {
+            RequestedRange::Full => (StatusCode::OK, 0, self.len),
+            RequestedRange::Partial(start, end) => {
+                headers.typed_insert(ContentRange::bytes(start..=end, self.len).unwrap());
+                (StatusCode::PARTIAL_CONTENT, start, end + 1)
+            }
+            RequestedRange::Unsatisfiable => {
+                headers.typed_insert(ContentRange::unsatisfied_bytes(self.len));
+                return Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response());
+            }
+        };
+
+        let mut file = File::open(&self.path).await?;
+        if start > 0 {
+            file.seek(SeekFrom::Start(start)).await?;
+        }
+        let body = StreamBody::new(ReaderStream::new(file.take(end - start)));
+
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(self.content_type));
+        if let Some(encoding) = self.content_encoding {
+            headers.insert(
+                CONTENT_ENCODING,
+                HeaderValue::from_static(encoding.header_value()),
+            );
+        }
+        headers.typed_insert(ContentLength(end - start));
+
+        Ok((status, headers, body).into_response())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RequestedRange {
+    Full,
+    // Inclusive, like the header
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+// Only a single range is supported, anything asking for several gets the whole
+// file which is always allowed. So is ignoring a range when `If-Range` shows
+// the client's partial copy is out of date.
+fn requested_range(
+    request_headers: &HeaderMap,
+    etag: &ETag,
+    modified: SystemTime,
+    len: u64,
+) -> RequestedRange {
+    let range = match request_headers.typed_get::<Range>() {
+        Some(range) => range,
+        None => return RequestedRange::Full,
+    };
+
+    if let Some(if_range) = request_headers.typed_get::<IfRange>() {
+        if if_range.is_modified(Some(etag), Some(&LastModified::from(modified))) {
+            return RequestedRange::Full;
+        }
+    }
+
+    let mut ranges = range.iter();
+    let (start, end) = match (ranges.next(), ranges.next()) {
+        (Some(bounds), None) => bounds,
+        _ => return RequestedRange::Full,
+    };
+
+    let last = match len.checked_sub(1) {
+        Some(last) => last,
+        None => return RequestedRange::Unsatisfiable,
+    };
+
+    match (start, end) {
+        (Bound::Included(start), Bound::Included(end)) if start <= last && start <= end => {
+            RequestedRange::Partial(start, end.min(last))
+        }
+        (Bound::Included(start), Bound::Unbounded) if start <= last => {
+            RequestedRange::Partial(start, last)
+        }
+        // A suffix, `bytes=-500` is the last 500 bytes
+        (Bound::Unbounded, Bound::Included(suffix)) if suffix > 0 => {
+            RequestedRange::Partial(len.saturating_sub(suffix), last)
+        }
+        _ => RequestedRange::Unsatisfiable,
+    }
+}
+
+fn save_derivative(
+    root: &Path,
+    name: &str,
+    derivative: Derivative,
+    bytes: &[u8],
+) -> Result<(), Error> {
+    let name = derivative.filename(name);
+    let path = resolve_new(root, Path::new(&name), DERIVATIVE_EXTENSIONS)?;
+
+    tracing::info!("Saving {:?}: {}", derivative, path.display());
+
+    replace_file(&path, |tmp| Ok(std::fs::write(tmp, bytes)?))?;
+
+    Ok(())
+}
+
+// Files are written next to where they go and renamed over the one being
+// served, so a request never gets a half written file while it's remade. The
+// temporary name starts with a dot, which `resolve` never serves.
+fn replace_file(path: &Path, write: impl FnOnce(&Path) -> Result<(), Error>) -> Result<(), Error> {
+    let name = path
+        .file_name()
+        .ok_or(Error::InvalidPath)?
+        .to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.{}.tmp", name, Uuid::new_v4()));
+
+    match write(&tmp).and_then(|_| Ok(std::fs::rename(&tmp, path)?)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = std::fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+// The copies are still removed when the file itself has already gone
+async fn delete_with_derivatives(root: &Path, name: &str) -> Result<(), Error> {
+    match resolve(root, name, IMAGE_EXTENSIONS) {
+        Ok(path) => tokio::fs::remove_file(path).await?,
+        Err(Error::NotFound) => {}
+        Err(e) => return Err(e),
+    }
+
+    for derivative in Derivative::ALL {
+        let name = derivative.filename(name);
+        match resolve(root, &name, DERIVATIVE_EXTENSIONS) {
+            Ok(path) => tokio::fs::remove_file(path).await?,
+            Err(Error::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+// Sends the best modern format copy of an image that the client accepts,
+// falling back to the original for older browsers and for images saved before
+// the copies were made.
+async fn open_negotiated_image(
+    root: &Path,
+    name: &str,
+    original: PathBuf,
+    cache_control: &'static str,
+    request_headers: &HeaderMap,
+) -> Result<StaticFile, Error> {
+    for derivative in derivatives::accepted_derivatives(request_headers) {
+        match resolve(root, &derivative.filename(name), DERIVATIVE_EXTENSIONS) {
+            Ok(path) => {
+                let file = StaticFile::open(path, derivative.content_type(), cache_control).await?;
+
+                return Ok(StaticFile {
+                    vary: Some("Accept"),
+                    ..file
+                });
+            }
+            Err(Error::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let content_type = sniff_image_content_type(&original).await?;
+    let file = StaticFile::open(original, content_type, cache_control).await?;
+
+    Ok(StaticFile {
+        vary: Some("Accept"),
+        ..file
+    })
+}
+
+// Finds an existing file directly inside `root`. Names are URL segments, which
+// axum has already percent-decoded, so anything that isn't a single plain file
+// name is rejected up front. Canonicalizing afterwards resolves symlinks, which
+// must also stay inside `root`.
+fn resolve(root: &Path, name: &str, extensions: &[&str]) -> Result<PathBuf, Error> {
+    let path = root.join(check_file_name(Path::new(name), extensions)?);
+
+    let canonical = path.canonicalize().map_err(|e| match e.kind() {
+        ErrorKind::NotFound => Error::NotFound,
+        _ => Error::IO(e),
+    })?;
+
+    if canonical.starts_with(root) && canonical.is_file() {
+        Ok(canonical)
+    } else {
+        tracing::warn!("Refusing to serve {} from {}", name, root.display());
+        Err(Error::InvalidPath)
+    }
+}
+
+// Like `resolve` but for a file that is about to be created
+fn resolve_new(root: &Path, name: &Path, extensions: &[&str]) -> Result<PathBuf, Error> {
+    let path = root.join(check_file_name(name, extensions)?);
+
+    let canonical_parent = path.parent().ok_or(Error::InvalidPath)?.canonicalize()?;
+
+    // An existing symlink would be followed when the file is written
+    let is_symlink = path
+        .symlink_metadata()
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(false);
+
+    if canonical_parent == root && !is_symlink {
+        Ok(path)
+    } else {
+        Err(Error::InvalidPath)
+    }
+}
+
+fn check_file_name<'a>(name: &'a Path, extensions: &[&str]) -> Result<&'a Path, Error> {
+    let mut components = name.components();
+
+    let is_plain_name = matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    );
+    let is_unsafe = name
+        .to_str()
+        .map(|n| n.starts_with('.') || n.contains(['\\', '\0']))
+        .unwrap_or(true);
+
+    let has_allowed_extension = match name.extension() {
+        Some(ext) => ext
+            .to_str()
+            .map(|ext| extensions.contains(&ext.to_lowercase().as_str()))
+            .unwrap_or(false),
+        None => extensions.contains(&""),
+    };
+
+    if is_plain_name && !is_unsafe && has_allowed_extension {
+        Ok(name)
+    } else {
+        Err(Error::InvalidPath)
+    }
+}
+
+async fn sniff_image_content_type(path: &Path) -> Result<&'static str, Error> {
+    let mut magic = Vec::with_capacity(MAGIC_BYTES_LEN);
+    File::open(path)
+        .await?
+        .take(MAGIC_BYTES_LEN as u64)
+        .read_to_end(&mut magic)
+        .await?;
+
+    Ok(image_content_type(&magic))
+}
+
+// Uploads are named after whatever the browser claimed they were, so trust the
+// contents rather than the extension.
+pub fn image_content_type(contents: &[u8]) -> &'static str {
+    let magic = &contents[..contents.len().min(MAGIC_BYTES_LEN)];
+
+    match image::guess_format(magic) {
+        Ok(ImageFormat::Png) => "image/png",
+        Ok(ImageFormat::Jpeg) => "image/jpeg",
+        Ok(ImageFormat::Gif) => "image/gif",
+        Ok(ImageFormat::WebP) => "image/webp",
+        Ok(ImageFormat::Tiff) => "image/tiff",
+        Ok(ImageFormat::Bmp) => "image/bmp",
+        Ok(ImageFormat::Avif) => "image/avif",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::symlink, time::Duration};
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    // A directory of its own under the system temp directory, removed again
+    // when the test is done
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("static-files-{}", Uuid::new_v4()));
+            std::fs::create_dir(&dir).unwrap();
+
+            TempDir(dir.canonicalize().unwrap())
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // A root to serve from, with a file next to it that must stay out of reach
+    fn setup() -> (TempDir, PathBuf) {
+        let dir = TempDir::new();
+        let root = dir.0.join("root");
+        std::fs::create_dir(&root).unwrap();
+
+        std::fs::write(root.join("image.jpg"), b"inside").unwrap();
+        std::fs::write(dir.0.join("secret.jpg"), b"outside").unwrap();
+
+        (dir, root)
+    }
+
+    fn is_invalid<T>(result: Result<T, Error>) -> bool {
+        matches!(result, Err(Error::InvalidPath))
+    }
+
+    #[test]
+    fn resolves_plain_names() {
+        let (_dir, root) = setup();
+
+        assert_eq!(
+            resolve(&root, "image.jpg", IMAGE_EXTENSIONS).unwrap(),
+            root.join("image.jpg")
+        );
+        assert!(matches!(
+            resolve(&root, "missing.jpg", IMAGE_EXTENSIONS),
+            Err(Error::NotFound)
+        ));
+        assert_eq!(
+            resolve_new(&root, Path::new("new.jpg"), IMAGE_EXTENSIONS).unwrap(),
+            root.join("new.jpg")
+        );
+    }
+
+    #[test]
+    fn rejects_parent_directories() {
+        let (_dir, root) = setup();
+
+        // What axum hands over for `%2e%2e`, once it's percent-decoded
+        for name in ["..", "../secret.jpg", "image.jpg/.."] {
+            assert!(
+                is_invalid(resolve(&root, name, IMAGE_EXTENSIONS)),
+                "{}",
+                name
+            );
+            assert!(
+                is_invalid(resolve_new(&root, Path::new(name), IMAGE_EXTENSIONS)),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn encoded_dots_are_only_a_name() {
+        let (_dir, root) = setup();
+
+        // Not decoded again, so it can only be a file inside the root
+        assert!(matches!(
+            resolve(&root, "%2e%2e", IMAGE_EXTENSIONS),
+            Err(Error::NotFound)
+        ));
+        assert_eq!(
+            resolve_new(&root, Path::new("%2e%2e"), IMAGE_EXTENSIONS).unwrap(),
+            root.join("%2e%2e")
+        );
+    }
+
+    #[test]
+    fn rejects_unsafe_names() {
+        let (_dir, root) = setup();
+
+        let secret = root.parent().unwrap().join("secret.jpg");
+        let names = [
+            "a/b.jpg",
+            "/etc/passwd",
+            secret.to_str().unwrap(),
+            ".hidden.jpg",
+            "a\\..\\secret.jpg",
+            "image.jpg\0.css",
+            "",
+        ];
+        for name in names {
+            assert!(
+                is_invalid(resolve(&root, name, IMAGE_EXTENSIONS)),
+                "{:?}",
+                name
+            );
+            assert!(
+                is_invalid(resolve_new(&root, Path::new(name), IMAGE_EXTENSIONS)),
+                "{:?}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_other_extensions() {
+        let (_dir, root) = setup();
+        std::fs::write(root.join("script.js"), b"").unwrap();
+
+        assert!(is_invalid(resolve(&root, "script.js", IMAGE_EXTENSIONS)));
+        assert!(is_invalid(resolve(&root, "image.jpg", STYLE_EXTENSIONS)));
+        assert!(is_invalid(resolve_new(
+            &root,
+            Path::new("page.html"),
+            IMAGE_EXTENSIONS
+        )));
+
+        // Extensions aren't case sensitive
+        std::fs::write(root.join("upper.JPG"), b"").unwrap();
+        assert!(resolve(&root, "upper.JPG", IMAGE_EXTENSIONS).is_ok());
+    }
+
+    #[test]
+    fn rejects_symlinks_out_of_the_root() {
+        let (dir, root) = setup();
+        symlink(dir.0.join("secret.jpg"), root.join("link.jpg")).unwrap();
+        symlink(&dir.0, root.join("up")).unwrap();
+
+        assert!(is_invalid(resolve(&root, "link.jpg", IMAGE_EXTENSIONS)));
+        assert!(is_invalid(resolve(&root, "up", IMAGE_EXTENSIONS)));
+    }
+
+    #[test]
+    fn allows_symlinks_within_the_root() {
+        let (_dir, root) = setup();
+        symlink(root.join("image.jpg"), root.join("alias.jpg")).unwrap();
+
+        assert_eq!(
+            resolve(&root, "alias.jpg", IMAGE_EXTENSIONS).unwrap(),
+            root.join("image.jpg")
+        );
+    }
+
+    #[test]
+    fn refuses_to_write_through_symlinks() {
+        let (dir, root) = setup();
+        symlink(dir.0.join("secret.jpg"), root.join("link.jpg")).unwrap();
+        // Dangling, so writing would create a file outside the root
+        symlink(dir.0.join("created.jpg"), root.join("dangling.jpg")).unwrap();
+
+        assert!(is_invalid(resolve_new(
+            &root,
+            Path::new("link.jpg"),
+            IMAGE_EXTENSIONS
+        )));
+        assert!(is_invalid(resolve_new(
+            &root,
+            Path::new("dangling.jpg"),
+            IMAGE_EXTENSIONS
+        )));
+    }
+
+    fn range_headers(headers: &[(&'static str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+
+        map
+    }
+
+    fn etag() -> ETag {
+        "\"abc\"".parse().unwrap()
+    }
+
+    fn range(headers: &[(&'static str, &str)], len: u64) -> RequestedRange {
+        requested_range(&range_headers(headers), &etag(), UNIX_EPOCH, len)
+    }
+
+    #[test]
+    fn serves_everything_without_a_range() {
+        assert_eq!(range(&[], 10), RequestedRange::Full);
+    }
+
+    #[test]
+    fn serves_single_ranges() {
+        assert_eq!(
+            range(&[("range", "bytes=0-3")], 10),
+            RequestedRange::Partial(0, 3)
+        );
+        assert_eq!(
+            range(&[("range", "bytes=4-")], 10),
+            RequestedRange::Partial(4, 9)
+        );
+        // The end is cut back to the file
+        assert_eq!(
+            range(&[("range", "bytes=8-100")], 10),
+            RequestedRange::Partial(8, 9)
+        );
+    }
+
+    #[test]
+    fn serves_suffix_ranges() {
+        assert_eq!(
+            range(&[("range", "bytes=-3")], 10),
+            RequestedRange::Partial(7, 9)
+        );
+        // Longer than the file is the whole file
+        assert_eq!(
+            range(&[("range", "bytes=-30")], 10),
+            RequestedRange::Partial(0, 9)
+        );
+        assert_eq!(
+            range(&[("range", "bytes=-0")], 10),
+            RequestedRange::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn rejects_ranges_past_the_end() {
+        assert_eq!(
+            range(&[("range", "bytes=10-")], 10),
+            RequestedRange::Unsatisfiable
+        );
+        assert_eq!(
+            range(&[("range", "bytes=10-20")], 10),
+            RequestedRange::Unsatisfiable
+        );
+        assert_eq!(
+            range(&[("range", "bytes=0-")], 0),
+            RequestedRange::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn serves_everything_for_several_ranges() {
+        assert_eq!(
+            range(&[("range", "bytes=0-1, 4-5")], 10),
+            RequestedRange::Full
+        );
+    }
+
+    #[test]
+    fn ignores_the_range_when_if_range_is_out_of_date() {
+        assert_eq!(
+            range(&[("range", "bytes=0-3"), ("if-range", "\"abc\"")], 10),
+            RequestedRange::Partial(0, 3)
+        );
+        assert_eq!(
+            range(&[("range", "bytes=0-3"), ("if-range", "\"other\"")], 10),
+            RequestedRange::Full
+        );
+        // Weak tags can't be used to put a file back together
+        assert_eq!(
+            range(&[("range", "bytes=0-3"), ("if-range", "W/\"abc\"")], 10),
+            RequestedRange::Full
+        );
+
+        // A date only matches if the file hasn't changed since
+        let modified = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let mut headers = range_headers(&[("range", "bytes=0-3")]);
+        headers.typed_insert(IfRange::date(modified));
+        assert_eq!(
+            requested_range(&headers, &etag(), modified, 10),
+            RequestedRange::Partial(0, 3)
+        );
+        assert_eq!(
+            requested_range(&headers, &etag(), modified + Duration::from_secs(60), 10),
+            RequestedRange::Full
+        );
+    }
+
+    async fn response(headers: &[(&'static str, &str)]) -> Response {
+        let dir = TempDir::new();
+        let path = dir.0.join("file.txt");
+        std::fs::write(&path, b"0123456789").unwrap();
+
+        StaticFile::open(path, "text/plain", REVALIDATE)
+            .await
+            .unwrap()
+            .into_response(&range_headers(headers))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn responds_with_the_range() {
+        let res = response(&[("range", "bytes=2-4")]).await;
+
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()["content-range"], "bytes 2-4/10");
+        assert_eq!(res.headers()["content-length"], "3");
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"234");
+    }
+
+    #[tokio::test]
+    async fn responds_416_to_unsatisfiable_ranges() {
+        let res = response(&[("range", "bytes=20-")]).await;
+
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(res.headers()["content-range"], "bytes */10");
     }
 }
@@ -1,8 +1,163 @@
-use image::imageops::FilterType;
+use std::sync::Arc;
+
+use clap::ValueEnum;
+use image::{imageops::FilterType, DynamicImage, ImageFormat};
+use sha2::{Digest, Sha256};
 
 use crate::model::forms::image::Rectangle;
 
-use super::static_files::StaticFiles;
+use super::{
+    derivatives::Derivative, formats, placeholder::Placeholder, smartcrop::suggest_crop,
+    static_files::StaticFiles,
+};
+
+/// The profile served from `/thumbs/{file}`, which thumbnails were made with
+/// before there were others
+pub const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ThumbnailFit {
+    /// The whole image, scaled to fit inside the profile's shape
+    Contain,
+    /// Scaled to fill the profile's shape, cutting off the edges that don't fit
+    Cover,
+}
+
+/// A size and shape of thumbnail, served from `/thumbs/{name}/{file}`
+#[derive(Clone, Debug)]
+pub struct ThumbnailProfile {
+    pub name: String,
+    /// Length of the longest edge, in pixels
+    pub size: u32,
+    /// Width to height, or the image's own when missing
+    pub aspect: Option<(u32, u32)>,
+    pub fit: ThumbnailFit,
+}
+
+impl ThumbnailProfile {
+    fn default_profile() -> Self {
+        ThumbnailProfile {
+            name: DEFAULT_PROFILE.to_owned(),
+            size: 400,
+            aspect: None,
+            fit: ThumbnailFit::Contain,
+        }
+    }
+
+    pub fn is_square(&self) -> bool {
+        matches!(self.aspect, Some((width, height)) if width == height)
+    }
+
+    /// Where a square profile crops an image when it has no crop of its own
+    pub fn suggest_crop(&self, image: &DynamicImage) -> Option<Rectangle> {
+        match self.aspect {
+            Some(aspect) if self.is_square() => Some(suggest_crop(image, aspect)),
+            _ => None,
+        }
+    }
+
+    // Where the longest edge is `size` and the other follows the aspect ratio
+    fn frame(&self, width: u32, height: u32) -> (u32, u32) {
+        let (width, height) = self.aspect.unwrap_or((width, height));
+        if width >= height {
+            let ratio = width as f32 / self.size as f32;
+            (self.size, ((height as f32 / ratio) as u32).max(1))
+        } else {
+            let ratio = height as f32 / self.size as f32;
+            (((width as f32 / ratio) as u32).max(1), self.size)
+        }
+    }
+}
+
+// `square:400:1x1:cover` is a 400px square filled with the image, and
+// `small:200:original:contain` keeps each image's own shape
+pub fn parse_thumbnail_profile(value: &str) -> Result<ThumbnailProfile, String> {
+    let invalid = || {
+        format!(
+            "`{}` is not a thumbnail profile, expected name:size:aspect:fit \
+            such as square:400:1x1:cover",
+            value
+        )
+    };
+
+    let parts: Vec<&str> = value.split(':').collect();
+    let [name, size, aspect, fit] = parts[..] else {
+        return Err(invalid());
+    };
+
+    // Used as a directory and in URLs
+    let name_valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+    if !name_valid {
+        return Err(format!(
+            "Thumbnail profile name `{}` can only use lowercase letters, digits, - and _",
+            name
+        ));
+    }
+
+    let size = size.parse().ok().filter(|s| *s > 0).ok_or_else(invalid)?;
+
+    let aspect = match aspect {
+        "original" => None,
+        _ => {
+            let (width, height) = aspect.split_once('x').ok_or_else(invalid)?;
+            let width = width.parse().ok().filter(|w| *w > 0).ok_or_else(invalid)?;
+            let height = height.parse().ok().filter(|h| *h > 0).ok_or_else(invalid)?;
+            Some((width, height))
+        }
+    };
+
+    let fit = ThumbnailFit::from_str(fit, true).map_err(|_| invalid())?;
+
+    Ok(ThumbnailProfile {
+        name: name.to_owned(),
+        size,
+        aspect,
+        fit,
+    })
+}
+
+/// Every thumbnail made for each image. There's always a default profile,
+/// which keeps the original 400px thumbnails unless it's configured too.
+#[derive(Clone)]
+pub struct ThumbnailProfiles {
+    profiles: Arc<Vec<ThumbnailProfile>>,
+}
+
+impl ThumbnailProfiles {
+    pub fn new(mut profiles: Vec<ThumbnailProfile>) -> Self {
+        // The last of any with the same name wins
+        let mut seen = vec![];
+        profiles.reverse();
+        profiles.retain(|p| {
+            let is_new = !seen.contains(&p.name);
+            seen.push(p.name.clone());
+            is_new
+        });
+        profiles.reverse();
+
+        if !profiles.iter().any(|p| p.name == DEFAULT_PROFILE) {
+            profiles.insert(0, ThumbnailProfile::default_profile());
+        }
+
+        tracing::info!("Using thumbnail profiles: {:?}", profiles);
+
+        ThumbnailProfiles {
+            profiles: Arc::new(profiles),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ThumbnailProfile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    /// Every profile other than the default
+    pub fn named(&self) -> impl Iterator<Item = &ThumbnailProfile> {
+        self.profiles.iter().filter(|p| p.name != DEFAULT_PROFILE)
+    }
+}
 
 // Images use positive integers ONLY,
 // the cropping library can return double
@@ -25,18 +180,71 @@ impl From<Rectangle> for ImageRectangle {
     }
 }
 
-pub async fn make_thumbnail(
+/// What's recorded about a thumbnail once it's been made
+pub struct MadeThumbnail {
+    pub version: String,
+    pub placeholder: Placeholder,
+}
+
+impl MadeThumbnail {
+    fn new(thumb: &DynamicImage) -> anyhow::Result<Self> {
+        Ok(MadeThumbnail {
+            version: thumbnail_version(thumb),
+            placeholder: Placeholder::new(thumb)?,
+        })
+    }
+}
+
+/// Makes the profile's thumbnail of an image, saved as `web_filename`.
+/// Square profiles crop to a suggested part of the image when they aren't
+/// given a crop.
+pub fn make_thumbnail(
     filename: &str,
+    web_filename: &str,
+    profile: &ThumbnailProfile,
     crop_rect: Option<Rectangle>,
     static_files: &StaticFiles,
-) -> anyhow::Result<()> {
-    let image_path = static_files.get_image_path(filename);
+) -> anyhow::Result<MadeThumbnail> {
+    let image_path = static_files.get_image_path(filename)?;
 
-    let mut image = image::open(&image_path)?;
+    let (image, original_format) = formats::open(&image_path)?;
+    let format = formats::saved_format(web_filename, original_format, &image);
 
     tracing::debug!("Successfully loaded full size image {}", filename);
 
-    if let Some(rect) = crop_rect {
+    let thumb = render_thumbnail(filename, image, profile, crop_rect);
+
+    static_files.save_thumb(&profile.name, web_filename, &thumb, format)?;
+
+    save_thumbnail_derivatives(&profile.name, web_filename, &thumb, static_files)?;
+
+    MadeThumbnail::new(&thumb)
+}
+
+/// The thumbnail a crop would make, encoded as it would be saved but without
+/// touching the one being served
+pub fn preview_thumbnail(
+    filename: &str,
+    web_filename: &str,
+    profile: &ThumbnailProfile,
+    crop_rect: Rectangle,
+    static_files: &StaticFiles,
+) -> anyhow::Result<(Vec<u8>, ImageFormat)> {
+    let (image, original_format) = formats::open(&static_files.get_image_path(filename)?)?;
+    let format = formats::saved_format(web_filename, original_format, &image);
+
+    let thumb = render_thumbnail(filename, image, profile, Some(crop_rect));
+
+    Ok((formats::encode(&thumb, format)?, format))
+}
+
+fn render_thumbnail(
+    filename: &str,
+    mut image: DynamicImage,
+    profile: &ThumbnailProfile,
+    crop_rect: Option<Rectangle>,
+) -> DynamicImage {
+    if let Some(rect) = crop_rect.or_else(|| profile.suggest_crop(&image)) {
         let ImageRectangle {
             x,
             y,
@@ -48,25 +256,78 @@ pub async fn make_thumbnail(
         tracing::debug!("Cropped image {}", filename);
     }
 
-    let (nwidth, nheight) = if image.width() > image.height() {
-        let ratio = image.width() as f32 / 400.0;
-        let height = image.height() as f32 / ratio;
+    let (nwidth, nheight) = profile.frame(image.width(), image.height());
+
+    tracing::debug!("Resizing image {} for {} thumbnail", filename, profile.name);
+    let thumb = match (profile.aspect, profile.fit) {
+        (Some(_), ThumbnailFit::Cover) => {
+            image.resize_to_fill(nwidth, nheight, FilterType::Lanczos3)
+        }
+        (Some(_), ThumbnailFit::Contain) => image.resize(nwidth, nheight, FilterType::Lanczos3),
+        (None, _) => DynamicImage::ImageRgba8(image::imageops::resize(
+            &image,
+            nwidth,
+            nheight,
+            FilterType::Lanczos3,
+        )),
+    };
+    tracing::debug!("Successfully resized image {}", filename);
+
+    thumb
+}
+
+/// The crop a square profile would use for an image, for the cropper to start
+/// from
+pub fn suggest_thumbnail_crop(
+    filename: &str,
+    profile: &ThumbnailProfile,
+    static_files: &StaticFiles,
+) -> anyhow::Result<Option<Rectangle>> {
+    if !profile.is_square() {
+        return Ok(None);
+    }
+
+    let (image, _) = formats::open(&static_files.get_image_path(filename)?)?;
+
+    Ok(profile.suggest_crop(&image))
+}
+
+/// Makes the modern format copies of an existing thumbnail, for thumbnails
+/// made before they were.
+pub fn make_thumbnail_derivatives(
+    web_filename: &str,
+    static_files: &StaticFiles,
+) -> anyhow::Result<MadeThumbnail> {
+    let (thumb, _) = formats::open(&static_files.get_thumb_path(DEFAULT_PROFILE, web_filename)?)?;
 
-        (400, height as u32)
-    } else {
-        let ratio = image.height() as f32 / 400.0;
-        let width = image.width() as f32 / ratio;
+    save_thumbnail_derivatives(DEFAULT_PROFILE, web_filename, &thumb, static_files)?;
 
-        tracing::info!("Resizing: {} / {} = {width}", image.height(), ratio);
+    MadeThumbnail::new(&thumb)
+}
 
-        (width as u32, 400)
-    };
+// A hash of the pixels, which the saved file and its copies are all made from,
+// so the same crop keeps the same URL
+fn thumbnail_version(thumb: &DynamicImage) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(thumb.width().to_be_bytes());
+    hasher.update(thumb.height().to_be_bytes());
+    hasher.update(thumb.as_bytes());
 
-    tracing::debug!("Resizing image {}", filename);
-    let thumb = image::imageops::resize(&image, nwidth, nheight, FilterType::Lanczos3);
-    tracing::debug!("Successfully resized image {}", filename);
+    base64::encode_config(&hasher.finalize()[..9], base64::URL_SAFE_NO_PAD)
+}
 
-    static_files.save_thumb(filename, &thumb)?;
+fn save_thumbnail_derivatives(
+    profile: &str,
+    filename: &str,
+    thumb: &DynamicImage,
+    static_files: &StaticFiles,
+) -> anyhow::Result<()> {
+    for derivative in Derivative::ALL {
+        tracing::debug!("Encoding thumbnail {} as {:?}", filename, derivative);
+        let bytes = derivative.encode(thumb)?;
+
+        static_files.save_thumb_derivative(profile, filename, derivative, &bytes)?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,146 @@
+use std::path::Path;
+
+use axum::{
+    extract::multipart::Field,
+    headers::{ContentLength, HeaderMapExt},
+    http::HeaderMap,
+};
+use image::io::Reader as ImageReader;
+use tokio::{fs::File, io::AsyncWriteExt};
+
+use crate::model::error::Error;
+
+const MB: u64 = 1024 * 1024;
+
+/// How much can be uploaded at once. Checked as the upload arrives, rather
+/// than once it's all been read, so a huge upload can't use up the memory or
+/// disk before it's turned away.
+#[derive(Clone, Copy, Debug)]
+pub struct UploadLimits {
+    max_file_size: u64,
+    max_request_size: u64,
+    max_image_dimension: u32,
+}
+
+impl UploadLimits {
+    pub fn new(max_file_mb: u64, max_request_mb: u64, max_image_dimension: u32) -> Self {
+        UploadLimits {
+            max_file_size: max_file_mb * MB,
+            max_request_size: max_request_mb * MB,
+            max_image_dimension,
+        }
+    }
+
+    /// Starts counting a request's body, turning it away straight away if it
+    /// says it's too big
+    pub fn budget(&self, request_headers: &HeaderMap) -> Result<UploadBudget, Error> {
+        if let Some(ContentLength(len)) = request_headers.typed_get() {
+            if len > self.max_request_size {
+                return Err(self.request_too_large());
+            }
+        }
+
+        Ok(UploadBudget {
+            limits: *self,
+            remaining: self.max_request_size,
+            spent: false,
+        })
+    }
+
+    /// Checks an image's size from its header, before anything is decoded. A
+    /// small file can claim to be enormous, and decoding it would try to
+    /// allocate all of that.
+    pub fn check_dimensions(&self, path: &Path) -> Result<(), Error> {
+        let (width, height) = ImageReader::open(path)?
+            .with_guessed_format()?
+            .into_dimensions()
+            .map_err(|_| Error::UnsupportedImage)?;
+
+        if width > self.max_image_dimension || height > self.max_image_dimension {
+            return Err(Error::UploadTooLarge(format!(
+                "The image is {}x{} pixels, images can be at most {} pixels on each side. \
+                Please upload a smaller copy.",
+                width, height, self.max_image_dimension
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn request_too_large(&self) -> Error {
+        Error::UploadTooLarge(format!(
+            "Uploads can be at most {}MB in total. Please upload fewer or smaller files.",
+            self.max_request_size / MB
+        ))
+    }
+
+    fn file_too_large(&self) -> Error {
+        Error::UploadTooLarge(format!(
+            "Files can be at most {}MB. Please upload a smaller copy, such as a JPEG \
+            export of the scan.",
+            self.max_file_size / MB
+        ))
+    }
+}
+
+/// What's left of a request's size limit as its multipart fields are read
+pub struct UploadBudget {
+    limits: UploadLimits,
+    remaining: u64,
+    spent: bool,
+}
+
+impl UploadBudget {
+    pub async fn read_text(&mut self, mut field: Field<'_>) -> Result<String, Error> {
+        let mut text = vec![];
+        while let Some(chunk) = field.chunk().await? {
+            self.take(chunk.len() as u64)?;
+            text.extend_from_slice(&chunk);
+        }
+
+        String::from_utf8(text).map_err(|_| Error::IllegalStateError("Form field isn't UTF-8"))
+    }
+
+    /// Streams a file field to disk. A partly written file is left for the
+    /// caller's staging area to clean up. A file that's too large is still
+    /// read to the end, so the fields after it can be read too.
+    pub async fn save_file(&mut self, mut field: Field<'_>, path: &Path) -> Result<(), Error> {
+        let mut file = File::create(path).await?;
+
+        let mut len = 0;
+        while let Some(chunk) = field.chunk().await? {
+            self.take(chunk.len() as u64)?;
+
+            len += chunk.len() as u64;
+            if len <= self.limits.max_file_size {
+                file.write_all(&chunk).await?;
+            }
+        }
+        file.flush().await?;
+
+        if len > self.limits.max_file_size {
+            return Err(self.limits.file_too_large());
+        }
+
+        Ok(())
+    }
+
+    /// True once the request has gone over its limit, after which nothing
+    /// more of it can be read
+    pub fn is_spent(&self) -> bool {
+        self.spent
+    }
+
+    fn take(&mut self, len: u64) -> Result<(), Error> {
+        match self.remaining.checked_sub(len) {
+            Some(remaining) => {
+                self.remaining = remaining;
+                Ok(())
+            }
+            None => {
+                self.spent = true;
+                Err(self.limits.request_too_large())
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::{path::Path, sync::Arc};
+
+use ab_glyph::{point, Font, FontVec, PxScale, ScaleFont};
+use clap::ValueEnum;
+use image::{imageops::FilterType, DynamicImage, Rgba, RgbaImage};
+
+use crate::model::error::Error;
+
+// Text is drawn once this big and scaled down to fit each image
+const TEXT_SIZE: f32 = 200.0;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+/// A mark stamped on the display copies of images. Originals and thumbnails
+/// are left alone.
+#[derive(Clone)]
+pub struct Watermark {
+    mark: Arc<DynamicImage>,
+    position: WatermarkPosition,
+    opacity: f32,
+    scale: f32,
+}
+
+impl Watermark {
+    pub fn logo(
+        logo_path: &Path,
+        position: WatermarkPosition,
+        opacity: f32,
+        scale: f32,
+    ) -> Result<Self, Error> {
+        tracing::info!("Using watermark logo: {}", logo_path.display());
+
+        let logo = image::open(logo_path)?;
+
+        Ok(Self::new(logo, position, opacity, scale))
+    }
+
+    /// White text, drawn in the given TrueType or OpenType font
+    pub fn text(
+        text: &str,
+        font_path: &Path,
+        position: WatermarkPosition,
+        opacity: f32,
+        scale: f32,
+    ) -> Result<Self, Error> {
+        tracing::info!("Using watermark text: {}", text);
+
+        let font = FontVec::try_from_vec(std::fs::read(font_path)?)
+            .map_err(|_| Error::IllegalStateError("Unreadable watermark font"))?;
+        let font = font.as_scaled(PxScale::from(TEXT_SIZE));
+
+        let mut glyphs = vec![];
+        let mut caret = 0.0;
+        let mut previous = None;
+        for c in text.chars() {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                caret += font.kern(previous, id);
+            }
+            glyphs.push(id.with_scale_and_position(TEXT_SIZE, point(caret, font.ascent())));
+            caret += font.h_advance(id);
+            previous = Some(id);
+        }
+
+        let mut mark = RgbaImage::new(
+            (caret.ceil() as u32).max(1),
+            (font.height().ceil() as u32).max(1),
+        );
+        for glyph in glyphs {
+            let Some(outline) = font.outline_glyph(glyph) else {
+                // Spaces have nothing to draw
+                continue;
+            };
+
+            let bounds = outline.px_bounds();
+            outline.draw(|x, y, coverage| {
+                let x = bounds.min.x as i64 + x as i64;
+                let y = bounds.min.y as i64 + y as i64;
+                if x < 0 || y < 0 || x >= mark.width() as i64 || y >= mark.height() as i64 {
+                    return;
+                }
+
+                // Glyphs can overlap, so keep whichever covers the pixel most
+                let pixel = mark.get_pixel_mut(x as u32, y as u32);
+                let alpha = pixel[3].max((coverage * 255.0).round() as u8);
+                *pixel = Rgba([255, 255, 255, alpha]);
+            });
+        }
+
+        Ok(Self::new(
+            DynamicImage::ImageRgba8(mark),
+            position,
+            opacity,
+            scale,
+        ))
+    }
+
+    fn new(mark: DynamicImage, position: WatermarkPosition, opacity: f32, scale: f32) -> Self {
+        Watermark {
+            mark: Arc::new(mark),
+            position,
+            opacity: opacity.clamp(0.0, 1.0),
+            scale: scale.clamp(0.01, 1.0),
+        }
+    }
+
+    /// Stamps the mark on a copy of the image, sized relative to its width
+    pub fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        let (width, height) = (image.width(), image.height());
+        let margin = width.min(height) / 50;
+
+        let mark_width = ((width as f32 * self.scale).round() as u32).max(1);
+        let mut mark = self
+            .mark
+            .resize(mark_width, height.max(1), FilterType::Lanczos3)
+            .to_rgba8();
+        for pixel in mark.pixels_mut() {
+            pixel[3] = (pixel[3] as f32 * self.opacity).round() as u8;
+        }
+
+        let right = width.saturating_sub(mark.width() + margin);
+        let bottom = height.saturating_sub(mark.height() + margin);
+        let (x, y) = match self.position {
+            WatermarkPosition::TopLeft => (margin, margin),
+            WatermarkPosition::TopRight => (right, margin),
+            WatermarkPosition::BottomLeft => (margin, bottom),
+            WatermarkPosition::BottomRight => (right, bottom),
+            WatermarkPosition::Center => (
+                width.saturating_sub(mark.width()) / 2,
+                height.saturating_sub(mark.height()) / 2,
+            ),
+        };
+
+        let mut canvas = image.to_rgba8();
+        image::imageops::overlay(&mut canvas, &mark, x as i64, y as i64);
+
+        let canvas = DynamicImage::ImageRgba8(canvas);
+        if image.color().has_alpha() {
+            canvas
+        } else {
+            DynamicImage::ImageRgb8(canvas.to_rgb8())
+        }
+    }
+}
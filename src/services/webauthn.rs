@@ -0,0 +1,725 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use ciborium::value::Value;
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use rand::RngCore;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::model::{
+    credential::Credential,
+    error::Error,
+    forms::passkey::{FinishPasskeyLogin, RegisterPasskey},
+};
+
+const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+// Logins can be started by anyone, so this many challenges at most are kept,
+// dropping the oldest first
+const MAX_PENDING_CHALLENGES: usize = 1000;
+
+// Authenticator data flags, see https://www.w3.org/TR/webauthn-2/#flags
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+// COSE identifiers for an ECDSA P-256 key used with SHA-256 (ES256), which is
+// the only algorithm we offer to authenticators.
+const COSE_KEY_TYPE_EC2: i128 = 2;
+const COSE_ALG_ES256: i128 = -7;
+const COSE_CURVE_P256: i128 = 1;
+
+enum Ceremony {
+    Registration { username: String },
+    Authentication { username: Option<String> },
+}
+
+struct PendingChallenge {
+    ceremony: Ceremony,
+    expires: Instant,
+}
+
+/// Relying party side of the WebAuthn registration and authentication
+/// ceremonies. Challenges are kept in memory and are single use.
+#[derive(Clone)]
+pub struct Webauthn {
+    rp_id: String,
+    origin: String,
+    pending: Arc<Mutex<HashMap<String, PendingChallenge>>>,
+}
+
+pub struct VerifiedCredential {
+    pub id: String,
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: u32,
+    // Attested credential data and extensions
+    rest: &'a [u8],
+}
+
+impl Webauthn {
+    pub fn new(rp_id: String, origin: String) -> Self {
+        tracing::info!("Using WebAuthn relying party {} at {}", rp_id, origin);
+
+        Webauthn {
+            rp_id,
+            origin,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn is_secure_origin(&self) -> bool {
+        self.origin.starts_with("https://")
+    }
+
+    pub fn start_registration(
+        &self,
+        username: &str,
+        existing_credentials: &[Credential],
+    ) -> serde_json::Value {
+        let challenge = self.new_challenge(Ceremony::Registration {
+            username: username.to_string(),
+        });
+
+        let exclude_credentials: Vec<_> = existing_credentials
+            .iter()
+            .map(|c| json!({ "type": "public-key", "id": c.id }))
+            .collect();
+
+        json!({
+            "challenge": challenge,
+            "rp": { "id": self.rp_id, "name": "Jinwon Kim Art" },
+            "user": {
+                "id": encode(username.as_bytes()),
+                "name": username,
+                "displayName": username,
+            },
+            "pubKeyCredParams": [{ "type": "public-key", "alg": COSE_ALG_ES256 as i64 }],
+            "timeout": CHALLENGE_TIMEOUT.as_millis() as u64,
+            "attestation": "none",
+            "authenticatorSelection": {
+                "residentKey": "preferred",
+                "userVerification": "preferred",
+            },
+            "excludeCredentials": exclude_credentials,
+        })
+    }
+
+    pub fn finish_registration(
+        &self,
+        username: &str,
+        response: &RegisterPasskey,
+    ) -> Result<VerifiedCredential, Error> {
+        let client_data_json = decode(&response.client_data_json)?;
+        let client_data = self.check_client_data(&client_data_json, "webauthn.create")?;
+
+        match self.take_challenge(&client_data.challenge) {
+            Some(Ceremony::Registration { username: expected }) if expected == username => {}
+            _ => return Err(Error::Webauthn("Unknown or expired challenge")),
+        }
+
+        let attestation_object: Value =
+            ciborium::de::from_reader(decode(&response.attestation_object)?.as_slice())
+                .map_err(|_| Error::Webauthn("Malformed attestation object"))?;
+
+        // We ask for no attestation so the statement itself is ignored, all we
+        // need is the credential inside the authenticator data.
+        let auth_data = map_get(&attestation_object, |k| k.as_text() == Some("authData"))
+            .and_then(Value::as_bytes)
+            .ok_or(Error::Webauthn("Attestation object is missing authData"))?;
+
+        let auth_data = self.check_authenticator_data(auth_data)?;
+
+        if auth_data.flags & FLAG_ATTESTED_CREDENTIAL_DATA == 0 {
+            return Err(Error::Webauthn("No credential in authenticator data"));
+        }
+
+        // AAGUID (16 bytes), credential ID length (2 bytes), credential ID and
+        // then the COSE encoded public key.
+        let rest = auth_data.rest;
+        if rest.len() < 18 {
+            return Err(Error::Webauthn("Truncated attested credential data"));
+        }
+        let id_len = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+        let rest = &rest[18..];
+        if rest.len() < id_len {
+            return Err(Error::Webauthn("Truncated credential ID"));
+        }
+        let (credential_id, cose_key) = rest.split_at(id_len);
+
+        if decode(&response.id)? != credential_id {
+            return Err(Error::Webauthn("Credential ID mismatch"));
+        }
+
+        let cose_key: Value = ciborium::de::from_reader(cose_key)
+            .map_err(|_| Error::Webauthn("Malformed credential public key"))?;
+
+        Ok(VerifiedCredential {
+            id: encode(credential_id),
+            public_key: cose_key_to_sec1(&cose_key)?,
+            sign_count: auth_data.sign_count,
+        })
+    }
+
+    pub fn start_authentication(
+        &self,
+        username: Option<&str>,
+        credentials: &[Credential],
+    ) -> serde_json::Value {
+        let challenge = self.new_challenge(Ceremony::Authentication {
+            username: username.map(str::to_string),
+        });
+
+        let allow_credentials: Vec<_> = credentials
+            .iter()
+            .map(|c| json!({ "type": "public-key", "id": c.id }))
+            .collect();
+
+        json!({
+            "challenge": challenge,
+            "rpId": self.rp_id,
+            "timeout": CHALLENGE_TIMEOUT.as_millis() as u64,
+            "userVerification": "preferred",
+            "allowCredentials": allow_credentials,
+        })
+    }
+
+    /// Checks an assertion against the stored credential it claims to be
+    /// from, returning the authenticator's new signature counter.
+    pub fn finish_authentication(
+        &self,
+        response: &FinishPasskeyLogin,
+        credential: &Credential,
+    ) -> Result<u32, Error> {
+        let client_data_json = decode(&response.client_data_json)?;
+        let client_data = self.check_client_data(&client_data_json, "webauthn.get")?;
+
+        match self.take_challenge(&client_data.challenge) {
+            Some(Ceremony::Authentication { username: None }) => {}
+            Some(Ceremony::Authentication {
+                username: Some(expected),
+            }) if expected == credential.username => {}
+            _ => return Err(Error::Webauthn("Unknown or expired challenge")),
+        }
+
+        let auth_data_bytes = decode(&response.authenticator_data)?;
+        let auth_data = self.check_authenticator_data(&auth_data_bytes)?;
+
+        let key = VerifyingKey::from_sec1_bytes(&credential.public_key)
+            .map_err(|_| Error::Webauthn("Stored public key is invalid"))?;
+        let signature = Signature::from_der(&decode(&response.signature)?)
+            .map_err(|_| Error::Webauthn("Malformed signature"))?;
+
+        let mut signed = auth_data_bytes.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data_json));
+
+        key.verify(&signed, &signature)
+            .map_err(|_| Error::Webauthn("Invalid signature"))?;
+
+        // Authenticators that keep a counter must always increase it, going
+        // backwards is a sign that the credential has been cloned.
+        let stored_count = credential.sign_count as u32;
+        if (auth_data.sign_count != 0 || stored_count != 0) && auth_data.sign_count <= stored_count
+        {
+            return Err(Error::Webauthn("Signature counter did not increase"));
+        }
+
+        Ok(auth_data.sign_count)
+    }
+
+    fn new_challenge(&self, ceremony: Ceremony) -> String {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let challenge = encode(&bytes);
+
+        let mut pending = self.pending.lock().unwrap();
+        let now = Instant::now();
+        pending.retain(|_, p| p.expires > now);
+        while pending.len() >= MAX_PENDING_CHALLENGES {
+            let oldest = pending
+                .iter()
+                .min_by_key(|(_, p)| p.expires)
+                .map(|(c, _)| c.clone());
+            match oldest {
+                Some(oldest) => pending.remove(&oldest),
+                None => break,
+            };
+        }
+        pending.insert(
+            challenge.clone(),
+            PendingChallenge {
+                ceremony,
+                expires: now + CHALLENGE_TIMEOUT,
+            },
+        );
+
+        challenge
+    }
+
+    fn take_challenge(&self, challenge: &str) -> Option<Ceremony> {
+        let mut pending = self.pending.lock().unwrap();
+
+        pending
+            .remove(challenge)
+            .filter(|p| p.expires > Instant::now())
+            .map(|p| p.ceremony)
+    }
+
+    fn check_client_data(&self, json: &[u8], kind: &str) -> Result<ClientData, Error> {
+        let client_data: ClientData =
+            serde_json::from_slice(json).map_err(|_| Error::Webauthn("Malformed client data"))?;
+
+        if client_data.kind != kind {
+            return Err(Error::Webauthn("Unexpected ceremony type"));
+        }
+        if client_data.origin != self.origin {
+            return Err(Error::Webauthn("Unexpected origin"));
+        }
+
+        Ok(client_data)
+    }
+
+    fn check_authenticator_data<'a>(
+        &self,
+        bytes: &'a [u8],
+    ) -> Result<AuthenticatorData<'a>, Error> {
+        if bytes.len() < 37 {
+            return Err(Error::Webauthn("Truncated authenticator data"));
+        }
+
+        let auth_data = AuthenticatorData {
+            rp_id_hash: &bytes[..32],
+            flags: bytes[32],
+            sign_count: u32::from_be_bytes([bytes[33], bytes[34], bytes[35], bytes[36]]),
+            rest: &bytes[37..],
+        };
+
+        if auth_data.rp_id_hash != &Sha256::digest(self.rp_id.as_bytes())[..] {
+            return Err(Error::Webauthn("Unexpected relying party"));
+        }
+        if auth_data.flags & FLAG_USER_PRESENT == 0 {
+            return Err(Error::Webauthn("User was not present"));
+        }
+
+        Ok(auth_data)
+    }
+}
+
+fn cose_key_to_sec1(cose_key: &Value) -> Result<Vec<u8>, Error> {
+    let int_param = |label: i128| {
+        map_get(cose_key, |k| k.as_integer().map(i128::from) == Some(label))
+            .and_then(Value::as_integer)
+            .map(i128::from)
+    };
+    let bytes_param = |label: i128| {
+        map_get(cose_key, |k| k.as_integer().map(i128::from) == Some(label))
+            .and_then(Value::as_bytes)
+    };
+
+    if int_param(1) != Some(COSE_KEY_TYPE_EC2)
+        || int_param(3) != Some(COSE_ALG_ES256)
+        || int_param(-1) != Some(COSE_CURVE_P256)
+    {
+        return Err(Error::Webauthn("Only ES256 passkeys are supported"));
+    }
+
+    let (Some(x), Some(y)) = (bytes_param(-2), bytes_param(-3)) else {
+        return Err(Error::Webauthn(
+            "Credential public key is missing coordinates",
+        ));
+    };
+
+    // Uncompressed SEC1 point
+    let mut sec1 = vec![0x04];
+    sec1.extend_from_slice(x);
+    sec1.extend_from_slice(y);
+
+    VerifyingKey::from_sec1_bytes(&sec1)
+        .map_err(|_| Error::Webauthn("Credential public key is not on the curve"))?;
+
+    Ok(sec1)
+}
+
+fn map_get(map: &Value, matches: impl Fn(&Value) -> bool) -> Option<&Value> {
+    map.as_map()?
+        .iter()
+        .find(|(k, _)| matches(k))
+        .map(|(_, v)| v)
+}
+
+pub fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, Error> {
+    base64::decode_config(value, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| Error::Webauthn("Malformed base64url value"))
+}
+
+#[cfg(test)]
+mod tests {
+    use p256::ecdsa::{signature::Signer, SigningKey};
+    use rand::rngs::OsRng;
+
+    use super::*;
+
+    const RP_ID: &str = "localhost";
+    const ORIGIN: &str = "http://localhost:3000";
+    const USERNAME: &str = "user";
+
+    // A software authenticator with a single ES256 credential, building what a
+    // browser would send from its authenticator's responses
+    struct Authenticator {
+        key: SigningKey,
+        credential_id: Vec<u8>,
+        sign_count: u32,
+        rp_id: &'static str,
+        origin: &'static str,
+        flags: u8,
+    }
+
+    impl Authenticator {
+        fn new() -> Self {
+            let mut credential_id = vec![0u8; 16];
+            rand::thread_rng().fill_bytes(&mut credential_id);
+
+            Authenticator {
+                key: SigningKey::random(&mut OsRng),
+                credential_id,
+                sign_count: 0,
+                rp_id: RP_ID,
+                origin: ORIGIN,
+                flags: FLAG_USER_PRESENT,
+            }
+        }
+
+        fn register(&mut self, options: &serde_json::Value) -> RegisterPasskey {
+            let client_data_json = self.client_data("webauthn.create", options);
+
+            // The attested credential data, AAGUID, credential ID length, the
+            // credential ID and its public key
+            let mut credential_data = vec![0u8; 16];
+            credential_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            credential_data.extend_from_slice(&self.credential_id);
+            credential_data.extend_from_slice(&self.cose_key());
+
+            let auth_data = self.auth_data(FLAG_ATTESTED_CREDENTIAL_DATA, &credential_data);
+            let attestation_object = Value::Map(vec![
+                (Value::Text("fmt".into()), Value::Text("none".into())),
+                (Value::Text("attStmt".into()), Value::Map(vec![])),
+                (Value::Text("authData".into()), Value::Bytes(auth_data)),
+            ]);
+
+            RegisterPasskey {
+                name: "Software".to_owned(),
+                id: encode(&self.credential_id),
+                client_data_json: encode(&client_data_json),
+                attestation_object: encode(&cbor(&attestation_object)),
+            }
+        }
+
+        fn login(&mut self, options: &serde_json::Value) -> FinishPasskeyLogin {
+            self.sign_count += 1;
+
+            let client_data_json = self.client_data("webauthn.get", options);
+            let auth_data = self.auth_data(0, &[]);
+
+            let mut signed = auth_data.clone();
+            signed.extend_from_slice(&Sha256::digest(&client_data_json));
+            let signature: Signature = self.key.sign(&signed);
+
+            FinishPasskeyLogin {
+                id: encode(&self.credential_id),
+                client_data_json: encode(&client_data_json),
+                authenticator_data: encode(&auth_data),
+                signature: encode(signature.to_der().as_bytes()),
+            }
+        }
+
+        fn client_data(&self, kind: &str, options: &serde_json::Value) -> Vec<u8> {
+            serde_json::to_vec(&json!({
+                "type": kind,
+                "challenge": options["challenge"],
+                "origin": self.origin,
+                "crossOrigin": false,
+            }))
+            .unwrap()
+        }
+
+        fn auth_data(&self, extra_flags: u8, rest: &[u8]) -> Vec<u8> {
+            let mut auth_data = Sha256::digest(self.rp_id.as_bytes()).to_vec();
+            auth_data.push(self.flags | extra_flags);
+            auth_data.extend_from_slice(&self.sign_count.to_be_bytes());
+            auth_data.extend_from_slice(rest);
+
+            auth_data
+        }
+
+        fn cose_key(&self) -> Vec<u8> {
+            let point = self.key.verifying_key().to_encoded_point(false);
+            let int = |i: i128| Value::Integer(i.try_into().unwrap());
+
+            cbor(&Value::Map(vec![
+                (int(1), int(COSE_KEY_TYPE_EC2)),
+                (int(3), int(COSE_ALG_ES256)),
+                (int(-1), int(COSE_CURVE_P256)),
+                (int(-2), Value::Bytes(point.x().unwrap().to_vec())),
+                (int(-3), Value::Bytes(point.y().unwrap().to_vec())),
+            ]))
+        }
+    }
+
+    fn cbor(value: &Value) -> Vec<u8> {
+        let mut bytes = vec![];
+        ciborium::ser::into_writer(value, &mut bytes).unwrap();
+
+        bytes
+    }
+
+    fn webauthn() -> Webauthn {
+        Webauthn::new(RP_ID.to_owned(), ORIGIN.to_owned())
+    }
+
+    fn credential(verified: VerifiedCredential) -> Credential {
+        Credential {
+            id: verified.id,
+            username: USERNAME.to_owned(),
+            name: "Software".to_owned(),
+            public_key: verified.public_key,
+            sign_count: verified.sign_count as i64,
+            created_at: String::new(),
+            last_used_at: None,
+        }
+    }
+
+    // A registered credential, and the authenticator holding it
+    fn registered(webauthn: &Webauthn) -> (Authenticator, Credential) {
+        let mut authenticator = Authenticator::new();
+        let options = webauthn.start_registration(USERNAME, &[]);
+        let verified = webauthn
+            .finish_registration(USERNAME, &authenticator.register(&options))
+            .unwrap();
+
+        (authenticator, credential(verified))
+    }
+
+    fn is_rejected<T>(result: Result<T, Error>, reason: &str) -> bool {
+        matches!(result, Err(Error::Webauthn(r)) if r == reason)
+    }
+
+    #[test]
+    fn registers_and_logs_in() {
+        let webauthn = webauthn();
+        let (mut authenticator, credential) = registered(&webauthn);
+
+        assert_eq!(credential.id, encode(&authenticator.credential_id));
+        assert_eq!(
+            credential.public_key,
+            authenticator
+                .key
+                .verifying_key()
+                .to_encoded_point(false)
+                .as_bytes()
+        );
+
+        let options =
+            webauthn.start_authentication(Some(USERNAME), std::slice::from_ref(&credential));
+        let sign_count = webauthn
+            .finish_authentication(&authenticator.login(&options), &credential)
+            .unwrap();
+        assert_eq!(sign_count, 1);
+
+        // Discoverable credentials, where the username isn't known up front
+        let options = webauthn.start_authentication(None, &[]);
+        let credential = Credential {
+            sign_count: sign_count as i64,
+            ..credential
+        };
+        assert_eq!(
+            webauthn
+                .finish_authentication(&authenticator.login(&options), &credential)
+                .unwrap(),
+            2
+        );
+    }
+
+    #[test]
+    fn rejects_the_wrong_origin() {
+        let webauthn = webauthn();
+
+        let mut authenticator = Authenticator::new();
+        authenticator.origin = "https://evil.example";
+        let options = webauthn.start_registration(USERNAME, &[]);
+        assert!(is_rejected(
+            webauthn.finish_registration(USERNAME, &authenticator.register(&options)),
+            "Unexpected origin"
+        ));
+
+        let (mut authenticator, credential) = registered(&webauthn);
+        authenticator.origin = "https://evil.example";
+        let options = webauthn.start_authentication(Some(USERNAME), &[]);
+        assert!(is_rejected(
+            webauthn.finish_authentication(&authenticator.login(&options), &credential),
+            "Unexpected origin"
+        ));
+    }
+
+    #[test]
+    fn rejects_the_wrong_relying_party() {
+        let webauthn = webauthn();
+
+        let mut authenticator = Authenticator::new();
+        authenticator.rp_id = "evil.example";
+        let options = webauthn.start_registration(USERNAME, &[]);
+        assert!(is_rejected(
+            webauthn.finish_registration(USERNAME, &authenticator.register(&options)),
+            "Unexpected relying party"
+        ));
+
+        let (mut authenticator, credential) = registered(&webauthn);
+        authenticator.rp_id = "evil.example";
+        let options = webauthn.start_authentication(Some(USERNAME), &[]);
+        assert!(is_rejected(
+            webauthn.finish_authentication(&authenticator.login(&options), &credential),
+            "Unexpected relying party"
+        ));
+    }
+
+    #[test]
+    fn requires_user_presence() {
+        let webauthn = webauthn();
+
+        let mut authenticator = Authenticator::new();
+        authenticator.flags = 0;
+        let options = webauthn.start_registration(USERNAME, &[]);
+        assert!(is_rejected(
+            webauthn.finish_registration(USERNAME, &authenticator.register(&options)),
+            "User was not present"
+        ));
+
+        let (mut authenticator, credential) = registered(&webauthn);
+        authenticator.flags = 0;
+        let options = webauthn.start_authentication(Some(USERNAME), &[]);
+        assert!(is_rejected(
+            webauthn.finish_authentication(&authenticator.login(&options), &credential),
+            "User was not present"
+        ));
+    }
+
+    #[test]
+    fn rejects_replayed_and_unknown_challenges() {
+        let webauthn = webauthn();
+        let (mut authenticator, credential) = registered(&webauthn);
+
+        let options = webauthn.start_authentication(Some(USERNAME), &[]);
+        let response = authenticator.login(&options);
+        let sign_count = webauthn
+            .finish_authentication(&response, &credential)
+            .unwrap();
+
+        let credential = Credential {
+            sign_count: sign_count as i64 - 1,
+            ..credential
+        };
+        assert!(is_rejected(
+            webauthn.finish_authentication(&response, &credential),
+            "Unknown or expired challenge"
+        ));
+
+        let made_up = json!({ "challenge": encode(b"made up") });
+        assert!(is_rejected(
+            webauthn.finish_authentication(&authenticator.login(&made_up), &credential),
+            "Unknown or expired challenge"
+        ));
+        assert!(is_rejected(
+            webauthn.finish_registration(USERNAME, &Authenticator::new().register(&made_up)),
+            "Unknown or expired challenge"
+        ));
+    }
+
+    #[test]
+    fn rejects_challenges_for_another_ceremony_or_user() {
+        let webauthn = webauthn();
+        let (mut authenticator, credential) = registered(&webauthn);
+
+        let options = webauthn.start_registration(USERNAME, &[]);
+        assert!(is_rejected(
+            webauthn.finish_authentication(&authenticator.login(&options), &credential),
+            "Unknown or expired challenge"
+        ));
+
+        let options = webauthn.start_authentication(Some("someone-else"), &[]);
+        assert!(is_rejected(
+            webauthn.finish_authentication(&authenticator.login(&options), &credential),
+            "Unknown or expired challenge"
+        ));
+    }
+
+    #[test]
+    fn rejects_a_signature_counter_going_backwards() {
+        let webauthn = webauthn();
+        let (mut authenticator, credential) = registered(&webauthn);
+
+        // Another copy of the credential has been used more since
+        let credential = Credential {
+            sign_count: 10,
+            ..credential
+        };
+        authenticator.sign_count = 4;
+
+        let options = webauthn.start_authentication(Some(USERNAME), &[]);
+        assert!(is_rejected(
+            webauthn.finish_authentication(&authenticator.login(&options), &credential),
+            "Signature counter did not increase"
+        ));
+    }
+
+    #[test]
+    fn keeps_a_bounded_number_of_challenges() {
+        let webauthn = webauthn();
+        let (mut authenticator, credential) = registered(&webauthn);
+
+        let first = webauthn.start_authentication(Some(USERNAME), &[]);
+        for _ in 0..MAX_PENDING_CHALLENGES {
+            webauthn.start_authentication(None, &[]);
+        }
+        assert_eq!(
+            webauthn.pending.lock().unwrap().len(),
+            MAX_PENDING_CHALLENGES
+        );
+
+        assert!(is_rejected(
+            webauthn.finish_authentication(&authenticator.login(&first), &credential),
+            "Unknown or expired challenge"
+        ));
+    }
+
+    #[test]
+    fn rejects_a_signature_from_another_key() {
+        let webauthn = webauthn();
+        let (mut authenticator, credential) = registered(&webauthn);
+        authenticator.key = SigningKey::random(&mut OsRng);
+
+        let options = webauthn.start_authentication(Some(USERNAME), &[]);
+        assert!(is_rejected(
+            webauthn.finish_authentication(&authenticator.login(&options), &credential),
+            "Invalid signature"
+        ));
+    }
+}
@@ -5,3 +5,5 @@ mkdir -p /opt/jinwonkim.art/styles
 mkdir -p /opt/jinwonkim.art/js
 mkdir -p /opt/jinwonkim.art/templates
 mkdir -p /opt/jinwonkim.art/thumbs
+mkdir -p /opt/jinwonkim.art/display
+mkdir -p /opt/jinwonkim.art/staging